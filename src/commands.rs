@@ -1,58 +1,69 @@
+mod adoption;
 mod canteen;
+mod cooldown;
+mod counter;
 mod drink;
 mod help;
-mod meow;
-mod twd2;
 
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::sync::Arc;
+use std::time::Duration;
 
 use async_trait::async_trait;
-use teloxide::adaptors::AutoSend;
-use teloxide::dispatching::UpdateWithCx;
+use futures::StreamExt;
+use teloxide::adaptors::{AutoSend, DefaultParseMode};
+use teloxide::dispatching::{Dispatcher, DispatcherHandlerRx, ShutdownToken, UpdateWithCx};
+use teloxide::error_handlers::OnError;
 use teloxide::requests::{Requester, RequesterExt};
-use teloxide::types::{BotCommand as BotCommandDescriptor, Message};
-use teloxide::utils::command::BotCommand;
+use teloxide::types::{BotCommand as BotCommandDescriptor, CallbackQuery, Message, ParseMode};
+use teloxide::utils::command::parse_command;
 use teloxide::Bot;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_stream::wrappers::UnboundedReceiverStream;
 
+use crate::commands::adoption::AdoptionCommandHandler;
 use crate::commands::canteen::CanteenCommandHandler;
+use crate::commands::cooldown::{CooldownMiddleware, CooldownRule};
+use crate::commands::counter::CounterCommandHandler;
 use crate::commands::drink::DrinkCommandHandler;
 use crate::commands::help::HelpCommandHandler;
-use crate::commands::meow::MeowCommandHandler;
-use crate::commands::twd2::Twd2CommandHandler;
 use crate::config::Config;
+use crate::services::storage::Storage;
 
 /// A command REPL bot that serves the thufood bot commands in a REPL.
 #[derive(Clone, Default)]
 pub struct CommandRepl {
     handlers: Vec<Arc<dyn CommandHandler>>,
+
+    /// Indices of the handlers declaring the commands, keyed by command name.
+    routes: HashMap<String, usize>,
+
+    /// The commands listed in the command menu.
+    commands: Vec<BotCommandDescriptor>,
 }
 
 impl CommandRepl {
     /// Create a new `CommandRepl` from the given application configuration.
     pub fn from_config(config: &Config) -> Result<Arc<Self>, Box<dyn Error>> {
-        fn create_command_handler<H>(
-            config: &Config,
-        ) -> Result<Arc<dyn CommandHandler>, Box<dyn Error>>
-        where
-            H: 'static + CommandHandler,
-        {
-            let handler = H::new(config)?;
-            Ok(Arc::new(handler))
-        }
-
         let mut dispatcher = Self::default();
+        let storage = crate::services::storage::open(&config.storage)?;
 
-        let factories = [
-            create_command_handler::<CanteenCommandHandler>,
-            create_command_handler::<DrinkCommandHandler>,
-            create_command_handler::<HelpCommandHandler>,
-            create_command_handler::<MeowCommandHandler>,
-            create_command_handler::<Twd2CommandHandler>,
-        ];
-        for f in factories {
-            let handler = f(config)?;
-            dispatcher.handlers.push(handler);
+        for kind in handler_kinds() {
+            let index = dispatcher.handlers.len();
+            dispatcher.handlers.push((kind.create)(config, &storage)?);
+            for spec in (kind.commands)(config) {
+                if dispatcher.routes.insert(spec.name.clone(), index).is_some() {
+                    return Err(format!("Command /{} is declared more than once", spec.name).into());
+                }
+                if spec.visibility == Visibility::Public {
+                    dispatcher.commands.push(BotCommandDescriptor {
+                        command: spec.name,
+                        description: spec.description,
+                    });
+                }
+            }
         }
 
         Ok(Arc::new(dispatcher))
@@ -60,31 +71,97 @@ impl CommandRepl {
 
     /// Run the REPL.
     pub async fn run(self: Arc<Self>, token: String, name: String) {
-        let bot = Bot::new(token).auto_send();
+        let bot = Bot::new(token).parse_mode(ParseMode::Html).auto_send();
 
         // Register all the commands provided by the bot.
-        match bot.set_my_commands(ALL_COMMANDS.clone()).await {
+        match bot.set_my_commands(self.commands.clone()).await {
             Ok(_) => (),
             Err(e) => {
                 log::warn!("Cannot set commands: {}", e);
             }
         }
 
-        let self_share = self.clone();
-        teloxide::commands_repl(bot, name, move |ctx, cmd| {
-            Self::handle_message(self_share.clone(), ctx, cmd)
-        })
-        .await;
+        let self_messages = self.clone();
+        let self_callbacks = self.clone();
+        let name = Arc::new(name);
+        let mut dispatcher = Dispatcher::new(bot)
+            .messages_handler(
+                move |rx: DispatcherHandlerRx<AutoSend<DefaultParseMode<Bot>>, Message>| {
+                    UnboundedReceiverStream::new(rx).for_each_concurrent(None, move |ctx| {
+                        let repl = self_messages.clone();
+                        let name = name.clone();
+                        async move {
+                            Self::handle_message(repl, ctx, name)
+                                .await
+                                .log_on_error()
+                                .await;
+                        }
+                    })
+                },
+            )
+            .callback_queries_handler(
+                move |rx: DispatcherHandlerRx<AutoSend<DefaultParseMode<Bot>>, CallbackQuery>| {
+                    UnboundedReceiverStream::new(rx).for_each_concurrent(None, move |ctx| {
+                        let repl = self_callbacks.clone();
+                        async move {
+                            Self::handle_callback_query(repl, ctx)
+                                .await
+                                .log_on_error()
+                                .await;
+                        }
+                    })
+                },
+            )
+            .setup_ctrlc_handler();
+        let token = dispatcher.shutdown_token();
+        tokio::select! {
+            _ = dispatcher.dispatch() => (),
+            _ = terminated(token) => {
+                log::warn!("The dispatcher did not shut down in time, stopping anyway");
+            }
+        }
+
+        for h in &self.handlers {
+            h.clone().shutdown();
+        }
     }
 
     async fn handle_message(
         self: Arc<Self>,
-        ctx: UpdateWithCx<AutoSend<Bot>, Message>,
-        cmd: Command,
+        ctx: UpdateWithCx<AutoSend<DefaultParseMode<Bot>>, Message>,
+        bot_name: Arc<String>,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
         for h in &self.handlers {
-            if h.clone().accept(&cmd) {
-                h.clone().handle(ctx, cmd).await?;
+            h.clone().observe(&ctx.update);
+        }
+
+        let cmd = match ctx
+            .update
+            .text()
+            .and_then(|text| Command::parse(text, &bot_name))
+        {
+            Some(cmd) => cmd,
+            None => return Ok(()),
+        };
+
+        match self.routes.get(&cmd.name) {
+            Some(index) => self.handlers[*index].clone().handle(ctx, cmd).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn handle_callback_query(
+        self: Arc<Self>,
+        ctx: UpdateWithCx<AutoSend<DefaultParseMode<Bot>>, CallbackQuery>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let data = match &ctx.update.data {
+            Some(data) => data.clone(),
+            None => return Ok(()),
+        };
+
+        for h in &self.handlers {
+            if h.clone().accept_callback(&data) {
+                h.clone().handle_callback(ctx).await?;
                 break;
             }
         }
@@ -92,89 +169,221 @@ impl CommandRepl {
     }
 }
 
-#[derive(BotCommand)]
-#[command(rename = "lowercase", description = "支持的命令：")]
-enum Command {
-    #[command(description = "开始交互并显示帮助信息")]
-    Start,
+/// How long the dispatcher may take to shut down after SIGTERM before it is abandoned.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+/// Shut down the given dispatcher when the process receives SIGTERM, e.g. from `docker stop`.
+///
+/// This function returns if the dispatcher is still running `SHUTDOWN_GRACE` after the signal,
+/// e.g. because it cannot reach Telegram, so that the handlers still get the chance to save their
+/// pending changes before the process is killed.
+#[cfg(unix)]
+async fn terminated(token: ShutdownToken) {
+    let mut signals = match signal(SignalKind::terminate()) {
+        Ok(signals) => signals,
+        Err(e) => {
+            log::warn!("Cannot listen for SIGTERM: {}", e);
+            return std::future::pending().await;
+        }
+    };
+    loop {
+        signals.recv().await;
+        match token.shutdown() {
+            Ok(_) => {
+                log::info!("SIGTERM received, trying to shutdown the dispatcher...");
+                break;
+            }
+            Err(_) => {
+                log::info!("SIGTERM received, the dispatcher isn't running, ignoring the signal")
+            }
+        }
+    }
+    tokio::time::sleep(SHUTDOWN_GRACE).await;
+}
+
+#[cfg(not(unix))]
+async fn terminated(_token: ShutdownToken) {
+    std::future::pending().await
+}
+
+/// A command received by the bot.
+struct Command {
+    /// Name of the command, without the leading `/`.
+    name: String,
+
+    /// The text following the command, with the surrounding whitespaces removed.
+    args: String,
+}
+
+impl Command {
+    /// Parse a command from the given message text.
+    ///
+    /// This function returns `None` if the text is not a command addressed to the bot.
+    fn parse(text: &str, bot_name: &str) -> Option<Self> {
+        let (name, _) = parse_command(text, bot_name)?;
+        let args = text
+            .trim_start()
+            .split_once(char::is_whitespace)
+            .map(|(_, args)| args.trim())
+            .unwrap_or_default();
+        Some(Self {
+            name: name.to_lowercase(),
+            args: args.to_owned(),
+        })
+    }
+}
+
+/// Whether a command is shown to the users.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Visibility {
+    /// The command is listed in the command menu and the help text.
+    Public,
 
-    #[command(description = "显示帮助信息")]
-    Help,
+    /// The command is accepted but not listed, e.g. a command for the administrators.
+    Hidden,
+}
 
-    #[command(description = "随机选择一个餐厅")]
-    Canteen,
+/// Declaration of a command handled by a command handler.
+#[derive(Clone, Debug)]
+struct CommandSpec {
+    /// Name of the command, without the leading `/`.
+    name: String,
 
-    #[command(description = "线上喝奶茶")]
-    Milktea,
+    /// Synopsis of the arguments of the command shown in the help text, e.g. `物种 名字`.
+    args: String,
 
-    #[command(description = "线上喝卡布奇诺")]
-    Cappuccino,
+    description: String,
+    visibility: Visibility,
 
-    #[command(description = "线上生产饮品")]
-    ProduceDrink { drink_name: String },
+    /// Whether the command is built into the bot rather than defined in the configuration.
+    builtin: bool,
+}
 
-    #[command(description = "投喂猫咪")]
-    FeedMeow,
+impl CommandSpec {
+    /// Declare a public command built into the bot.
+    fn builtin(name: &str, args: &str, description: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            args: args.to_owned(),
+            description: description.to_owned(),
+            visibility: Visibility::Public,
+            builtin: true,
+        }
+    }
 
-    #[command(description = "使用 WD40 投喂猫咪")]
-    FeedMeowWd40,
+    /// Declare a public command defined in the configuration, which takes no arguments.
+    fn custom(name: &str, description: &str) -> Self {
+        Self {
+            builtin: false,
+            ..Self::builtin(name, "", description)
+        }
+    }
 
-    #[command(description = "使用酥酥万呆粒投喂二老师")]
-    FeedTwd2,
+    /// Hide the command from the command menu and the help text.
+    fn hidden(self) -> Self {
+        Self {
+            visibility: Visibility::Hidden,
+            ..self
+        }
+    }
 }
 
-lazy_static! {
-    static ref ALL_COMMANDS: Vec<BotCommandDescriptor> = vec![
-        BotCommandDescriptor {
-            command: String::from("start"),
-            description: String::from("开始交互并显示帮助信息"),
-        },
-        BotCommandDescriptor {
-            command: String::from("help"),
-            description: String::from("显示帮助信息"),
-        },
-        BotCommandDescriptor {
-            command: String::from("canteen"),
-            description: String::from("随机选择一个餐厅"),
-        },
-        BotCommandDescriptor {
-            command: String::from("milktea"),
-            description: String::from("线上喝奶茶"),
-        },
-        BotCommandDescriptor {
-            command: String::from("cappuccino"),
-            description: String::from("线上喝卡布奇诺"),
-        },
-        BotCommandDescriptor {
-            command: String::from("producedrink"),
-            description: String::from("线上生产饮品"),
-        },
-        BotCommandDescriptor {
-            command: String::from("feedmeow"),
-            description: String::from("投喂猫咪"),
-        },
-        BotCommandDescriptor {
-            command: String::from("feedmeowwd40"),
-            description: String::from("使用 WD40 投喂猫咪"),
-        },
-        BotCommandDescriptor {
-            command: String::from("feedtwd2"),
-            description: String::from("使用酥酥万呆粒投喂二老师"),
+/// Create a command handler from the application configuration and the storage.
+type HandlerFactory =
+    fn(&Config, &Arc<dyn Storage>) -> Result<Arc<dyn CommandHandler>, Box<dyn Error>>;
+
+/// A kind of command handler.
+struct HandlerKind {
+    create: HandlerFactory,
+    commands: fn(&Config) -> Vec<CommandSpec>,
+}
+
+impl HandlerKind {
+    fn of<H>() -> Self
+    where
+        H: 'static + CommandHandler,
+    {
+        Self {
+            create: |config, storage| Ok(Arc::new(H::new(config, storage)?)),
+            commands: H::commands,
         }
-    ];
+    }
+}
+
+/// Get the kinds of the command handlers of the bot, in the order their commands are listed.
+fn handler_kinds() -> [HandlerKind; 5] {
+    [
+        HandlerKind::of::<HelpCommandHandler>(),
+        HandlerKind::of::<CanteenCommandHandler>(),
+        HandlerKind::of::<DrinkCommandHandler>(),
+        HandlerKind::of::<CooldownMiddleware<CounterCommandHandler>>(),
+        HandlerKind::of::<CooldownMiddleware<AdoptionCommandHandler>>(),
+    ]
+}
+
+/// Get the declarations of all the commands provided by the bot under the given configuration.
+fn all_commands(config: &Config) -> Vec<CommandSpec> {
+    handler_kinds()
+        .iter()
+        .flat_map(|kind| (kind.commands)(config))
+        .collect()
+}
+
+/// Get the names of the commands built into the bot under the given configuration.
+pub fn builtin_command_names(config: &Config) -> HashSet<String> {
+    all_commands(config)
+        .into_iter()
+        .filter(|spec| spec.builtin)
+        .map(|spec| spec.name)
+        .collect()
 }
 
 #[async_trait]
 trait CommandHandler: Send + Sync {
-    fn new(config: &Config) -> Result<Self, Box<dyn Error>>
+    fn new(config: &Config, storage: &Arc<dyn Storage>) -> Result<Self, Box<dyn Error>>
     where
         Self: Sized;
 
-    fn accept(self: Arc<Self>, cmd: &Command) -> bool;
+    /// Declare the commands handled by the handler under the given configuration.
+    ///
+    /// Every command received by the bot is routed to the handler declaring it. The command menu
+    /// and the help text are also derived from the declarations.
+    fn commands(config: &Config) -> Vec<CommandSpec>
+    where
+        Self: Sized;
+
+    /// Observe a message received by the bot, whether or not it is a command.
+    fn observe(self: Arc<Self>, _message: &Message) {}
+
+    /// Release the resources held by the handler, e.g. write pending changes into files.
+    ///
+    /// This function is called once after the bot stops receiving updates.
+    fn shutdown(self: Arc<Self>) {}
+
+    /// Get the cooldown applied to the given command accepted by the handler.
+    ///
+    /// The cooldown is only enforced if the handler is wrapped in a `CooldownMiddleware`.
+    fn cooldown(self: Arc<Self>, _cmd: &Command) -> Option<CooldownRule> {
+        None
+    }
+
+    /// Determine whether the handler accepts callback queries with the given data.
+    fn accept_callback(self: Arc<Self>, _data: &str) -> bool {
+        false
+    }
+
+    /// Handle a callback query accepted by `accept_callback`.
+    async fn handle_callback(
+        self: Arc<Self>,
+        _ctx: UpdateWithCx<AutoSend<DefaultParseMode<Bot>>, CallbackQuery>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Ok(())
+    }
 
+    /// Handle a command declared by the handler.
     async fn handle(
         self: Arc<Self>,
-        ctx: UpdateWithCx<AutoSend<Bot>, Message>,
+        ctx: UpdateWithCx<AutoSend<DefaultParseMode<Bot>>, Message>,
         cmd: Command,
     ) -> Result<(), Box<dyn Error + Send + Sync>>;
 }
@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use teloxide::adaptors::{AutoSend, DefaultParseMode};
+use teloxide::dispatching::UpdateWithCx;
+use teloxide::types::Message;
+use teloxide::Bot;
+
+use crate::commands::cooldown::CooldownRule;
+use crate::commands::{Command, CommandHandler, CommandSpec};
+use crate::config::{Adoption, Config};
+use crate::services::counter::{CounterService, Profile};
+use crate::services::sanitizer::Sanitizer;
+use crate::services::storage::Storage;
+
+/// Key of the cooldown shared by feeding the adopted pets.
+const COOLDOWN_KEY: &str = "adoption";
+
+/// Handler of the `/adopt` and the `/feed` commands.
+///
+/// Each chat may adopt a pet of its own. The pet of a chat is counted by a counter of its own,
+/// stored under the key `pet.<chat ID>`, whose profile holds the name and the species of the pet.
+pub struct AdoptionCommandHandler {
+    storage: Arc<dyn Storage>,
+    config: Adoption,
+    sanitizer: Sanitizer,
+
+    /// Offset of the local time of the users from UTC, in seconds.
+    utc_offset: i64,
+
+    /// The counters of the pets loaded so far, keyed by chat ID.
+    pets: Mutex<HashMap<i64, Arc<CounterService>>>,
+
+    /// Serialize loading the counters of the pets, so that each counter is loaded only once.
+    loading: tokio::sync::Mutex<()>,
+}
+
+impl AdoptionCommandHandler {
+    /// Get the counter of the pet of the given chat, loading it from the storage if needed.
+    ///
+    /// Unless `adopting` is set, the counter is only loaded if the chat has adopted a pet, and
+    /// `None` is returned otherwise. Loading a counter starts its background tasks, which should
+    /// not be spent on the chats without pets.
+    async fn pet_of(
+        &self,
+        chat_id: i64,
+        adopting: bool,
+    ) -> Result<Option<Arc<CounterService>>, Box<dyn Error + Send + Sync>> {
+        if let Some(pet) = self.pets.lock().unwrap().get(&chat_id) {
+            return Ok(Some(pet.clone()));
+        }
+        let _loading = self.loading.lock().await;
+        if let Some(pet) = self.pets.lock().unwrap().get(&chat_id) {
+            return Ok(Some(pet.clone()));
+        }
+
+        let storage = self.storage.clone();
+        let key = format!("pet.{}", chat_id);
+        let utc_offset = self.utc_offset;
+        let pet = tokio::task::spawn_blocking(move || {
+            if !adopting {
+                let profile = CounterService::load_profile(&*storage, &key);
+                if profile.map_err(|e| e.to_string())?.is_none() {
+                    return Ok(None);
+                }
+            }
+            CounterService::new(storage, &key, utc_offset)
+                .map(Some)
+                .map_err(|e| e.to_string())
+        })
+        .await??;
+
+        let pet = pet.map(Arc::new);
+        if let Some(pet) = &pet {
+            self.pets.lock().unwrap().insert(chat_id, pet.clone());
+        }
+        Ok(pet)
+    }
+
+    async fn adopt(
+        &self,
+        ctx: UpdateWithCx<AutoSend<DefaultParseMode<Bot>>, Message>,
+        args: &str,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let args: Vec<_> = args.split_whitespace().collect();
+        let (species, name) = match args[..] {
+            [species, name] => (species, name),
+            _ => {
+                ctx.answer("用法：/adopt 物种 名字，例如 /adopt 橘猫 小橘")
+                    .await?;
+                return Ok(());
+            }
+        };
+        let (species, name) = match (
+            self.sanitizer.sanitize(species),
+            self.sanitizer.sanitize(name),
+        ) {
+            (Ok(species), Ok(name)) => (species, name),
+            (Err(e), _) | (_, Err(e)) => {
+                ctx.answer(e.to_string()).await?;
+                return Ok(());
+            }
+        };
+
+        let pet = match self.pet_of(ctx.update.chat_id(), true).await? {
+            Some(pet) => pet,
+            None => unreachable!(),
+        };
+        let profile = Profile {
+            name,
+            species,
+            created_at: crate::utils::get_unix_timestamp(),
+        };
+        let response = match pet.create_profile(profile.clone()) {
+            Ok(()) => format!("本群领养了{}", format_profile(&profile)),
+            Err(existing) => format!("本群已经有宠物了：{}", format_profile(&existing)),
+        };
+        ctx.answer(response).await?;
+        Ok(())
+    }
+
+    async fn feed(
+        &self,
+        ctx: UpdateWithCx<AutoSend<DefaultParseMode<Bot>>, Message>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let user = match crate::utils::get_message_sender(&ctx.update) {
+            Some(user) => user,
+            None => return Ok(()),
+        };
+        let pet = self.pet_of(ctx.update.chat_id(), false).await?;
+        let (pet, profile) = match pet.map(|pet| {
+            let profile = pet.profile();
+            (pet, profile)
+        }) {
+            Some((pet, Some(profile))) => (pet, profile),
+            _ => {
+                ctx.answer("本群还没有宠物，使用 /adopt 物种 名字 领养一只吧")
+                    .await?;
+                return Ok(());
+            }
+        };
+
+        let values = pet.increase(
+            ctx.update.chat_id(),
+            (user.id, &crate::utils::get_user_display_name(user)),
+        );
+        let now = crate::utils::get_unix_timestamp();
+        let (overfed, state) = pet.update_pet(|state| {
+            let overfed = state.feed(&self.config.traits, self.config.satiety, 0, now);
+            (overfed, state.clone())
+        });
+
+        let name = crate::utils::escape_html(&profile.name);
+        let mut response = format!(
+            "{} 投喂了{}，这是它的第 {} 次投喂\n饥饿度：{}/100\n精力：{}/100\n心情：{}",
+            crate::utils::get_user_mention(user),
+            format_profile(&profile),
+            values.global,
+            state.hunger.round(),
+            state.energy.round(),
+            state.mood(now)
+        );
+        if overfed {
+            response.push_str(&format!("\n{}吃撑了，心情变得暴躁了！", name));
+        }
+        ctx.answer(response).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CommandHandler for AdoptionCommandHandler {
+    fn new(config: &Config, storage: &Arc<dyn Storage>) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            storage: storage.clone(),
+            config: config.adoption.clone(),
+            sanitizer: Sanitizer::new(&config.sanitizer),
+            utc_offset: config.utc_offset_hours * 3600,
+            pets: Mutex::new(HashMap::new()),
+            loading: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    fn commands(_config: &Config) -> Vec<CommandSpec> {
+        vec![
+            CommandSpec::builtin("adopt", "物种 名字", "领养本群的宠物"),
+            CommandSpec::builtin("feed", "", "投喂本群的宠物"),
+        ]
+    }
+
+    fn cooldown(self: Arc<Self>, cmd: &Command) -> Option<CooldownRule> {
+        match cmd.name.as_str() {
+            "feed" => Some(CooldownRule {
+                key: String::from(COOLDOWN_KEY),
+                cooldown: self.config.cooldown.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    fn shutdown(self: Arc<Self>) {
+        for pet in self.pets.lock().unwrap().values() {
+            pet.flush();
+        }
+    }
+
+    async fn handle(
+        self: Arc<Self>,
+        ctx: UpdateWithCx<AutoSend<DefaultParseMode<Bot>>, Message>,
+        cmd: Command,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match cmd.name.as_str() {
+            "adopt" => self.adopt(ctx, &cmd.args).await,
+            "feed" => self.feed(ctx).await,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Format the species and the name of a pet.
+fn format_profile(profile: &Profile) -> String {
+    crate::utils::escape_html(&format!("{}「{}」", profile.species, profile.name))
+}
@@ -1,46 +1,152 @@
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::sync::Arc;
 
 use async_trait::async_trait;
 use rand::Rng;
-use teloxide::adaptors::AutoSend;
+use teloxide::adaptors::{AutoSend, DefaultParseMode};
 use teloxide::dispatching::UpdateWithCx;
 use teloxide::types::Message;
 use teloxide::Bot;
 
-use crate::commands::{Command, CommandHandler};
+use crate::commands::{Command, CommandHandler, CommandSpec};
 use crate::config::{Canteen, Config};
+use crate::services::preferences::PreferenceService;
+use crate::services::sanitizer::Sanitizer;
+use crate::services::storage::Storage;
 
-/// Handler of the `/canteen` command.
-#[derive(Clone, Debug)]
+/// Handler of the `/canteen` and the `/useset` commands.
 pub struct CanteenCommandHandler {
-    picker: CanteenPicker,
+    pickers: BTreeMap<String, CanteenPicker>,
+    default_set: String,
+    preferences: PreferenceService,
+    sanitizer: Sanitizer,
+}
+
+impl CanteenCommandHandler {
+    /// Get the name of the canteen set chosen by the given chat.
+    fn chat_set(&self, chat_id: i64) -> String {
+        match self.preferences.get(chat_id).canteen_set {
+            Some(set) if self.pickers.contains_key(&set) => set,
+            _ => self.default_set.clone(),
+        }
+    }
+
+    fn format_available_sets(&self) -> String {
+        let names: Vec<_> = self.pickers.keys().map(String::as_str).collect();
+        format!(
+            "可用的餐厅集合：{}",
+            crate::utils::escape_html(&names.join("、"))
+        )
+    }
+
+    fn format_unknown_set(&self, set_name: &str) -> String {
+        match self.sanitizer.sanitize(set_name) {
+            Ok(set_name) => format!(
+                "未知的餐厅集合：{}\n{}",
+                crate::utils::escape_html(&set_name),
+                self.format_available_sets()
+            ),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    async fn pick_canteen(
+        &self,
+        ctx: UpdateWithCx<AutoSend<DefaultParseMode<Bot>>, Message>,
+        set_name: &str,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let set_name = match parse_set_name(set_name) {
+            Some(set) => set.to_owned(),
+            None => self.chat_set(ctx.update.chat_id()),
+        };
+        let response = match self.pickers.get(&set_name) {
+            Some(picker) => crate::utils::escape_html(&picker.pick().name),
+            None => self.format_unknown_set(&set_name),
+        };
+        ctx.answer(response).await?;
+        Ok(())
+    }
+
+    async fn use_set(
+        &self,
+        ctx: UpdateWithCx<AutoSend<DefaultParseMode<Bot>>, Message>,
+        set_name: &str,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let chat_id = ctx.update.chat_id();
+        let response = match parse_set_name(set_name) {
+            Some(set) if self.pickers.contains_key(set) => {
+                self.preferences.update(chat_id, |p| {
+                    p.canteen_set = Some(set.to_owned());
+                });
+                format!(
+                    "本群的默认餐厅集合已设置为：{}",
+                    crate::utils::escape_html(set)
+                )
+            }
+            Some(set) => self.format_unknown_set(set),
+            None => format!(
+                "本群的默认餐厅集合：{}\n{}",
+                crate::utils::escape_html(&self.chat_set(chat_id)),
+                self.format_available_sets()
+            ),
+        };
+        ctx.answer(response).await?;
+        Ok(())
+    }
 }
 
 #[async_trait]
 impl CommandHandler for CanteenCommandHandler {
-    fn new(config: &Config) -> Result<Self, Box<dyn Error>> {
+    fn new(config: &Config, storage: &Arc<dyn Storage>) -> Result<Self, Box<dyn Error>> {
+        let pickers = config
+            .canteen_sets
+            .iter()
+            .map(|(name, canteens)| (name.clone(), CanteenPicker::new(canteens.clone())))
+            .collect();
         let handler = Self {
-            picker: CanteenPicker::new(config.canteens.clone()),
+            pickers,
+            default_set: config.default_canteen_set.clone(),
+            preferences: PreferenceService::new(storage.clone())?,
+            sanitizer: Sanitizer::new(&config.sanitizer),
         };
         Ok(handler)
     }
 
-    fn accept(self: Arc<Self>, cmd: &Command) -> bool {
-        match cmd {
-            Command::Canteen => true,
-            _ => false,
-        }
+    fn commands(_config: &Config) -> Vec<CommandSpec> {
+        vec![
+            CommandSpec::builtin("canteen", "[@集合名]", "随机选择一个餐厅"),
+            CommandSpec::builtin("useset", "集合名", "设置本群的默认餐厅集合"),
+        ]
+    }
+
+    fn shutdown(self: Arc<Self>) {
+        self.preferences.flush();
     }
 
     async fn handle(
         self: Arc<Self>,
-        ctx: UpdateWithCx<AutoSend<Bot>, Message>,
-        _cmd: Command,
+        ctx: UpdateWithCx<AutoSend<DefaultParseMode<Bot>>, Message>,
+        cmd: Command,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let canteen = self.picker.pick();
-        ctx.answer(canteen.name.clone()).await?;
-        Ok(())
+        match cmd.name.as_str() {
+            "canteen" => self.pick_canteen(ctx, &cmd.args).await,
+            "useset" => self.use_set(ctx, &cmd.args).await,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Parse the name of a canteen set given in a command argument.
+///
+/// The name may be optionally prefixed by `@`. This function returns `None` if no name is given.
+fn parse_set_name(arg: &str) -> Option<&str> {
+    let name = arg.trim();
+    let name = name.strip_prefix('@').unwrap_or(name);
+    if name.is_empty() {
+        None
+    } else {
+        Some(name)
     }
 }
 
@@ -57,7 +163,8 @@ struct CanteenPicker {
 impl CanteenPicker {
     /// Create a new `CanteenPicker` object.
     ///
-    /// This function panics if the given canteens list is empty.
+    /// This function panics if the given canteens list is empty or all the canteens have zero
+    /// weight. Such lists are rejected by `Config::validate`.
     fn new<T, I>(canteens: T) -> Self
     where
         T: IntoIterator<Item = Canteen, IntoIter = I>,
@@ -72,6 +179,7 @@ impl CanteenPicker {
             weight_sum += i.weight;
             weight_sums.push(weight_sum);
         }
+        assert!(weight_sum > 0);
 
         Self {
             canteens,
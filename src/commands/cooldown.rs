@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use teloxide::adaptors::{AutoSend, DefaultParseMode};
+use teloxide::dispatching::UpdateWithCx;
+use teloxide::types::{CallbackQuery, Message};
+use teloxide::Bot;
+
+use crate::commands::{Command, CommandHandler, CommandSpec};
+use crate::config::{Config, Cooldown};
+use crate::services::storage::Storage;
+
+/// The cooldown applied to a command.
+#[derive(Clone, Debug)]
+pub struct CooldownRule {
+    /// Identify the commands sharing the cooldown.
+    pub key: String,
+
+    pub cooldown: Cooldown,
+}
+
+/// Who a cooldown is tracked for.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum Scope {
+    User(i64),
+    Chat(i64),
+}
+
+/// Wrap a command handler and reject the commands used during their cooldowns, as given by
+/// `CommandHandler::cooldown`, before they reach the handler.
+pub struct CooldownMiddleware<H> {
+    inner: Arc<H>,
+
+    /// When the commands sharing each cooldown were last used, in seconds since the Unix epoch.
+    last_used: Mutex<HashMap<(String, Scope), u64>>,
+}
+
+impl<H> CooldownMiddleware<H> {
+    /// Get the number of seconds until the given cooldown ends for the given user in the given
+    /// chat, or record the use of the command if the cooldown has ended.
+    fn check(&self, rule: &CooldownRule, chat_id: i64, user_id: Option<i64>) -> Option<u64> {
+        let now = crate::utils::get_unix_timestamp();
+        let mut scopes = vec![(Scope::Chat(chat_id), rule.cooldown.chat_seconds)];
+        if let Some(user_id) = user_id {
+            scopes.push((Scope::User(user_id), rule.cooldown.user_seconds));
+        }
+        scopes.retain(|(_, seconds)| *seconds > 0);
+
+        let mut last_used = self.last_used.lock().unwrap();
+        let remaining = scopes
+            .iter()
+            .filter_map(|(scope, seconds)| {
+                let last = last_used.get(&(rule.key.clone(), *scope))?;
+                (last + seconds).checked_sub(now).filter(|r| *r > 0)
+            })
+            .max();
+        if remaining.is_none() {
+            for (scope, _) in scopes {
+                last_used.insert((rule.key.clone(), scope), now);
+            }
+        }
+        remaining
+    }
+}
+
+#[async_trait]
+impl<H> CommandHandler for CooldownMiddleware<H>
+where
+    H: 'static + CommandHandler,
+{
+    fn new(config: &Config, storage: &Arc<dyn Storage>) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            inner: Arc::new(H::new(config, storage)?),
+            last_used: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn commands(config: &Config) -> Vec<CommandSpec> {
+        H::commands(config)
+    }
+
+    fn observe(self: Arc<Self>, message: &Message) {
+        self.inner.clone().observe(message)
+    }
+
+    fn shutdown(self: Arc<Self>) {
+        self.inner.clone().shutdown()
+    }
+
+    fn cooldown(self: Arc<Self>, cmd: &Command) -> Option<CooldownRule> {
+        self.inner.clone().cooldown(cmd)
+    }
+
+    fn accept_callback(self: Arc<Self>, data: &str) -> bool {
+        self.inner.clone().accept_callback(data)
+    }
+
+    async fn handle_callback(
+        self: Arc<Self>,
+        ctx: UpdateWithCx<AutoSend<DefaultParseMode<Bot>>, CallbackQuery>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.inner.clone().handle_callback(ctx).await
+    }
+
+    async fn handle(
+        self: Arc<Self>,
+        ctx: UpdateWithCx<AutoSend<DefaultParseMode<Bot>>, Message>,
+        cmd: Command,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if let Some(rule) = self.inner.clone().cooldown(&cmd) {
+            let user_id = crate::utils::get_message_sender(&ctx.update).map(|u| u.id);
+            if let Some(seconds) = self.check(&rule, ctx.update.chat_id(), user_id) {
+                let response = crate::utils::escape_html(&rule.cooldown.message)
+                    .replace("{seconds}", &seconds.to_string());
+                ctx.answer(response).await?;
+                return Ok(());
+            }
+        }
+        self.inner.clone().handle(ctx, cmd).await
+    }
+}
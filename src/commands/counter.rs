@@ -0,0 +1,571 @@
+mod chart;
+mod milestone;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use rand::Rng;
+use teloxide::adaptors::{AutoSend, DefaultParseMode};
+use teloxide::dispatching::UpdateWithCx;
+use teloxide::payloads::SendPhotoSetters;
+use teloxide::requests::Requester;
+use teloxide::types::{InputFile, Message};
+use teloxide::Bot;
+
+use crate::commands::cooldown::CooldownRule;
+use crate::commands::{Command, CommandHandler, CommandSpec};
+use crate::config::{Config, Cooldown, FeedEvents, Milestones, Pet};
+use crate::services::counter::{CounterService, CounterValues, Feeder, Period};
+use crate::services::inventory::InventoryService;
+use crate::services::sanitizer::Sanitizer;
+use crate::services::storage::Storage;
+
+/// Maximum number of users shown in a leaderboard.
+const LEADERBOARD_SIZE: usize = 10;
+
+/// Key of the log recording the changes made to the counters by the administrators.
+const AUDIT_KEY: &str = "counter.audit";
+
+/// Usage of the `/counter` command.
+const MANAGE_USAGE: &str = "用法：/counter get|set|undo 计数器 [值]";
+
+/// Number of days shown in the chart of daily increments.
+const CHART_DAYS: u64 = 14;
+
+/// Handler of the counter commands defined in the configuration, e.g. the `/feedmeow` command.
+pub struct CounterCommandHandler {
+    counters: Vec<CounterEntry>,
+
+    /// The counter commands, keyed by command name.
+    commands: HashMap<String, CounterCommandEntry>,
+
+    /// Indices of the counters simulating pets, keyed by the commands showing the pets.
+    pet_commands: HashMap<String, usize>,
+
+    /// Indices of the counters, keyed by the commands showing their statistics.
+    stats_commands: HashMap<String, usize>,
+
+    /// The items dropped to the users increasing the counters.
+    inventory: InventoryService,
+
+    /// IDs of the users allowed to manage the counters.
+    admins: Vec<i64>,
+
+    /// Offset of the local time of the users from UTC, in seconds.
+    utc_offset: i64,
+
+    /// The storage holding the audit log of the changes made by the administrators.
+    storage: Arc<dyn Storage>,
+
+    sanitizer: Sanitizer,
+}
+
+/// A counter together with its milestones, pet, cooldown and random events.
+struct CounterEntry {
+    key: String,
+    name: String,
+    service: CounterService,
+    milestones: Option<Milestones>,
+    pet: Option<Pet>,
+    cooldown: Cooldown,
+    events: FeedEvents,
+}
+
+/// A counter command together with the index of the counter it increases.
+struct CounterCommandEntry {
+    counter: usize,
+    template: String,
+    satiety: u32,
+    energy: u32,
+}
+
+impl CounterCommandHandler {
+    /// Render the response to a counter command from the given template.
+    fn render(template: &str, values: CounterValues, user: &str) -> String {
+        crate::utils::escape_html(template)
+            .replace("{count}", &values.global.to_string())
+            .replace("{chat_count}", &values.chat.to_string())
+            .replace("{user_count}", &values.user.to_string())
+            .replace("{user}", user)
+    }
+
+    /// Send the state of the pet simulated by the given counter.
+    async fn show_pet(
+        &self,
+        ctx: UpdateWithCx<AutoSend<DefaultParseMode<Bot>>, Message>,
+        counter: &CounterEntry,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let config = match &counter.pet {
+            Some(config) => config,
+            None => unreachable!(),
+        };
+        let now = crate::utils::get_unix_timestamp();
+        let mut pet = counter.service.pet();
+        pet.decay(&config.traits, now);
+        let response = format!(
+            "{}的状态：\n饥饿度：{}/100\n精力：{}/100\n心情：{}",
+            crate::utils::escape_html(&counter.name),
+            pet.hunger.round(),
+            pet.energy.round(),
+            pet.mood(now)
+        );
+        ctx.answer(response).await?;
+        Ok(())
+    }
+
+    /// Send the statistics of the given counter and the chart of its daily increments in the
+    /// current chat.
+    async fn show_stats(
+        &self,
+        ctx: UpdateWithCx<AutoSend<DefaultParseMode<Bot>>, Message>,
+        counter: &CounterEntry,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let chat_id = ctx.update.chat_id();
+        let day_of = |time| crate::utils::local_day(time, self.utc_offset);
+        let today = day_of(crate::utils::get_unix_timestamp());
+        // The Unix epoch is a Thursday and weeks start on Monday.
+        let week_start = today - (today + 3) % 7;
+        let first_day = (today + 1).saturating_sub(CHART_DAYS).min(week_start);
+
+        let events = counter.service.events_since(
+            None,
+            crate::utils::local_day_start(first_day, self.utc_offset),
+        );
+        let count_since = |day: u64, chat_only: bool| {
+            events
+                .iter()
+                .filter(|e| day_of(e.time) >= day && (!chat_only || e.chat_id == chat_id))
+                .count()
+        };
+        let (global_total, chat_total) = counter.service.get(chat_id);
+
+        let mut daily: Vec<_> = ((today + 1).saturating_sub(CHART_DAYS)..=today)
+            .map(|day| (day, 0))
+            .collect();
+        for e in events.iter().filter(|e| e.chat_id == chat_id) {
+            if let Some(i) = day_of(e.time).checked_sub(daily[0].0) {
+                if let Some((_, count)) = daily.get_mut(i as usize) {
+                    *count += 1;
+                }
+            }
+        }
+
+        let caption = format!(
+            "{}投喂统计：\n今天：本群 {} 次，全局 {} 次\n本周：本群 {} 次，全局 {} 次\n\
+             总计：本群 {} 次，全局 {} 次\n图为本群最近 {} 天每天的投喂次数",
+            crate::utils::escape_html(&counter.name),
+            count_since(today, true),
+            count_since(today, false),
+            count_since(week_start, true),
+            count_since(week_start, false),
+            chat_total,
+            global_total,
+            CHART_DAYS
+        );
+        let chart = chart::render_daily_chart(&daily)?;
+        ctx.answer_photo(InputFile::memory("stats.png", chart))
+            .caption(caption)
+            .await?;
+        Ok(())
+    }
+
+    /// Send the leaderboards of a counter in the current chat and across all chats.
+    ///
+    /// `args` optionally gives the key of the counter and the period, i.e. `week` or `all`, in any
+    /// order. The first counter and all time are chosen by default.
+    async fn show_leaderboard(
+        &self,
+        ctx: UpdateWithCx<AutoSend<DefaultParseMode<Bot>>, Message>,
+        args: &str,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut counter = &self.counters[0];
+        let mut period = Period::All;
+        for arg in args.split_whitespace() {
+            match arg.to_lowercase().as_str() {
+                "week" => period = Period::Week,
+                "all" => period = Period::All,
+                key => match self.counters.iter().find(|c| c.key == key) {
+                    Some(c) => counter = c,
+                    None => {
+                        ctx.answer(self.format_unknown_counter(arg)).await?;
+                        return Ok(());
+                    }
+                },
+            }
+        }
+
+        let period_name = match period {
+            Period::Week => "本周",
+            Period::All => "总",
+        };
+        let chat = counter
+            .service
+            .leaderboard(Some(ctx.update.chat_id()), period);
+        let global = counter.service.leaderboard(None, period);
+        let response = format!(
+            "{}投喂{}排行榜：\n本群：\n{}\n全局：\n{}",
+            crate::utils::escape_html(&counter.name),
+            period_name,
+            format_leaderboard(&chat),
+            format_leaderboard(&global)
+        );
+        ctx.answer(response).await?;
+        Ok(())
+    }
+
+    fn format_unknown_counter(&self, key: &str) -> String {
+        match self.sanitizer.sanitize(key) {
+            Ok(key) => {
+                let keys: Vec<_> = self.counters.iter().map(|c| c.key.as_str()).collect();
+                crate::utils::escape_html(&format!(
+                    "未知的计数器：{}\n可用的计数器：{}",
+                    key,
+                    keys.join("、")
+                ))
+            }
+            Err(e) => e.to_string(),
+        }
+    }
+
+    /// Send the items collected by the sender of the message.
+    async fn show_inventory(
+        &self,
+        ctx: UpdateWithCx<AutoSend<DefaultParseMode<Bot>>, Message>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let user = match crate::utils::get_message_sender(&ctx.update) {
+            Some(user) => user,
+            None => return Ok(()),
+        };
+        let items = self.inventory.get(user.id);
+        let response = if items.is_empty() {
+            format!(
+                "{} 还没有收集到任何物品",
+                crate::utils::get_user_mention(user)
+            )
+        } else {
+            let lines: Vec<_> = items
+                .iter()
+                .map(|(item, count)| format!("{} × {}", crate::utils::escape_html(item), count))
+                .collect();
+            format!(
+                "{} 收集到的物品：\n{}",
+                crate::utils::get_user_mention(user),
+                lines.join("\n")
+            )
+        };
+        ctx.answer(response).await?;
+        Ok(())
+    }
+
+    /// Get, set or undo a counter on behalf of an administrator.
+    ///
+    /// `args` gives the action, the key of the counter and, for `set`, the new value of the
+    /// counter in the current chat. Every change is recorded in the audit log.
+    async fn manage(
+        &self,
+        ctx: UpdateWithCx<AutoSend<DefaultParseMode<Bot>>, Message>,
+        args: &str,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let admin = match crate::utils::get_message_sender(&ctx.update) {
+            Some(user) if self.admins.contains(&user.id) => user,
+            _ => {
+                ctx.answer("只有管理员可以管理计数器").await?;
+                return Ok(());
+            }
+        };
+        let args: Vec<_> = args.split_whitespace().collect();
+        let (action, key, value) = match args[..] {
+            [action @ ("get" | "undo"), key] => (action, key, None),
+            [action @ "set", key, value] => match value.parse::<u64>() {
+                Ok(value) => (action, key, Some(value)),
+                Err(_) => {
+                    ctx.answer(MANAGE_USAGE).await?;
+                    return Ok(());
+                }
+            },
+            _ => {
+                ctx.answer(MANAGE_USAGE).await?;
+                return Ok(());
+            }
+        };
+        let counter = match self.counters.iter().find(|c| c.key == key) {
+            Some(counter) => counter,
+            None => {
+                ctx.answer(self.format_unknown_counter(key)).await?;
+                return Ok(());
+            }
+        };
+
+        let chat_id = ctx.update.chat_id();
+        let name = crate::utils::escape_html(&counter.name);
+        let (response, change) = match (action, value) {
+            ("set", Some(value)) => match counter.service.set(chat_id, value) {
+                Some((global, chat, new_global)) => (
+                    format!(
+                        "已将{}的本群计数从 {} 改为 {}，全局计数从 {} 改为 {}",
+                        name, chat, value, global, new_global
+                    ),
+                    Some(format!("set {} {}", chat, value)),
+                ),
+                None => (format!("{} 太大了，全局计数会溢出", value), None),
+            },
+            ("undo", _) => match counter.service.undo(chat_id) {
+                Some((event, values)) => (
+                    format!(
+                        "已撤销本群对{}的最近一次投喂（用户 {}），本群计数现为 {}，全局计数现为 {}",
+                        name, event.user_id, values.chat, values.global
+                    ),
+                    Some(format!("undo {} {}", event.time, event.user_id)),
+                ),
+                None => (format!("本群没有可以撤销的{}投喂记录", name), None),
+            },
+            _ => {
+                let (global, chat) = counter.service.get(chat_id);
+                (
+                    format!("{}：本群 {} 次，全局 {} 次", name, chat, global),
+                    None,
+                )
+            }
+        };
+
+        if let Some(change) = change {
+            let record = format!(
+                "{} {} {} {} {}",
+                crate::utils::get_unix_timestamp(),
+                admin.id,
+                chat_id,
+                counter.key,
+                change
+            );
+            log::info!("Counter changed by an administrator: {}", record);
+            if let Err(e) = self.storage.append_log(AUDIT_KEY, &[record]) {
+                log::warn!("Failed to append to the audit log {}: {}", AUDIT_KEY, e);
+            }
+        }
+        ctx.answer(response).await?;
+        Ok(())
+    }
+
+    /// Send the celebration message if the given counter reaches a milestone.
+    async fn celebrate(
+        &self,
+        ctx: &UpdateWithCx<AutoSend<DefaultParseMode<Bot>>, Message>,
+        counter: &CounterEntry,
+        values: CounterValues,
+        user: &str,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let milestones = match &counter.milestones {
+            Some(milestones) if milestones.is_reached(values.global) => milestones,
+            _ => return Ok(()),
+        };
+
+        let message = ctx
+            .answer(Self::render(&milestones.template, values, user))
+            .await?;
+        if milestones.pin {
+            // The bot may not have the permission to pin messages, which is not fatal.
+            if let Err(e) = ctx
+                .requester
+                .pin_chat_message(message.chat_id(), message.id)
+                .await
+            {
+                log::warn!("Cannot pin celebration message: {}", e);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CommandHandler for CounterCommandHandler {
+    fn new(config: &Config, storage: &Arc<dyn Storage>) -> Result<Self, Box<dyn Error>> {
+        let utc_offset = config.utc_offset_hours * 3600;
+        let mut counters = Vec::with_capacity(config.counters.len());
+        let mut commands = HashMap::new();
+        let mut pet_commands = HashMap::new();
+        let mut stats_commands = HashMap::new();
+        for (i, counter) in config.counters.iter().enumerate() {
+            let name = if counter.name.is_empty() {
+                counter.key.clone()
+            } else {
+                counter.name.clone()
+            };
+            counters.push(CounterEntry {
+                key: counter.key.clone(),
+                name,
+                service: CounterService::new(storage.clone(), &counter.key, utc_offset)?,
+                milestones: counter.milestones.clone(),
+                pet: counter.pet.clone(),
+                cooldown: counter.cooldown.clone(),
+                events: counter.events.clone(),
+            });
+            if let Some(pet) = &counter.pet {
+                pet_commands.insert(pet.command.clone(), i);
+            }
+            if let Some(stats) = &counter.stats {
+                stats_commands.insert(stats.command.clone(), i);
+            }
+            for cmd in &counter.commands {
+                commands.insert(
+                    cmd.command.clone(),
+                    CounterCommandEntry {
+                        counter: i,
+                        template: cmd.template.clone(),
+                        satiety: cmd.satiety,
+                        energy: cmd.energy,
+                    },
+                );
+            }
+        }
+        Ok(Self {
+            counters,
+            commands,
+            pet_commands,
+            stats_commands,
+            inventory: InventoryService::new(storage.clone())?,
+            admins: config.admins.clone(),
+            utc_offset,
+            storage: storage.clone(),
+            sanitizer: Sanitizer::new(&config.sanitizer),
+        })
+    }
+
+    fn commands(config: &Config) -> Vec<CommandSpec> {
+        if config.counters.is_empty() {
+            return Vec::new();
+        }
+
+        let mut commands = vec![
+            CommandSpec::builtin("topfeeders", "[计数器] [week|all]", "显示投喂排行榜"),
+            CommandSpec::builtin("inventory", "", "查看投喂时收集到的物品"),
+            CommandSpec::builtin("counter", "get|set|undo 计数器 [值]", "管理计数器").hidden(),
+        ];
+        for counter in &config.counters {
+            commands.extend(
+                counter
+                    .commands
+                    .iter()
+                    .map(|cmd| CommandSpec::custom(&cmd.command, &cmd.description)),
+            );
+            if let Some(pet) = &counter.pet {
+                commands.push(CommandSpec::custom(&pet.command, &pet.description));
+            }
+            if let Some(stats) = &counter.stats {
+                commands.push(CommandSpec::custom(&stats.command, &stats.description));
+            }
+        }
+        commands
+    }
+
+    fn cooldown(self: Arc<Self>, cmd: &Command) -> Option<CooldownRule> {
+        let entry = self.commands.get(&cmd.name)?;
+        let counter = &self.counters[entry.counter];
+        Some(CooldownRule {
+            key: counter.key.clone(),
+            cooldown: counter.cooldown.clone(),
+        })
+    }
+
+    fn shutdown(self: Arc<Self>) {
+        for counter in &self.counters {
+            counter.service.flush();
+        }
+        self.inventory.flush();
+    }
+
+    async fn handle(
+        self: Arc<Self>,
+        ctx: UpdateWithCx<AutoSend<DefaultParseMode<Bot>>, Message>,
+        cmd: Command,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let entry = match cmd.name.as_str() {
+            "topfeeders" => return self.show_leaderboard(ctx, &cmd.args).await,
+            "inventory" => return self.show_inventory(ctx).await,
+            "counter" => return self.manage(ctx, &cmd.args).await,
+            name => {
+                if let Some(i) = self.pet_commands.get(name) {
+                    return self.show_pet(ctx, &self.counters[*i]).await;
+                }
+                if let Some(i) = self.stats_commands.get(name) {
+                    return self.show_stats(ctx, &self.counters[*i]).await;
+                }
+                &self.commands[name]
+            }
+        };
+        let user = match crate::utils::get_message_sender(&ctx.update) {
+            Some(user) => user,
+            None => return Ok(()),
+        };
+        let counter = &self.counters[entry.counter];
+        let user_name = crate::utils::get_user_display_name(user);
+        // A critical increment counts double, i.e. the counter is increased twice.
+        let critical = rand::thread_rng().gen_bool(counter.events.critical_chance);
+        let mut increments = vec![counter
+            .service
+            .increase(ctx.update.chat_id(), (user.id, &user_name))];
+        if critical {
+            increments.push(
+                counter
+                    .service
+                    .increase(ctx.update.chat_id(), (user.id, &user_name)),
+            );
+        }
+        let values = increments[increments.len() - 1];
+
+        let mention = crate::utils::get_user_mention(user);
+        let mut response = Self::render(&entry.template, values, &mention);
+        if critical {
+            response.push('\n');
+            response.push_str(&crate::utils::escape_html(&counter.events.critical_message));
+        }
+        for drop in &counter.events.drops {
+            if rand::thread_rng().gen_bool(drop.chance) {
+                self.inventory.add(user.id, &drop.item);
+                response.push('\n');
+                response.push_str(
+                    &crate::utils::escape_html(&drop.message).replace("{user}", &mention),
+                );
+            }
+        }
+        if let Some(config) = &counter.pet {
+            let now = crate::utils::get_unix_timestamp();
+            let overfed = counter
+                .service
+                .update_pet(|pet| pet.feed(&config.traits, entry.satiety, entry.energy, now));
+            if overfed {
+                response.push_str(&format!(
+                    "\n{}吃撑了，心情变得暴躁了！",
+                    crate::utils::escape_html(&counter.name)
+                ));
+            }
+        }
+        ctx.answer(response).await?;
+        for values in increments {
+            self.celebrate(&ctx, counter, values, &mention).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Format the top entries of a leaderboard.
+fn format_leaderboard(feeders: &[Feeder]) -> String {
+    if feeders.is_empty() {
+        return String::from("还没有人投喂过");
+    }
+    feeders
+        .iter()
+        .take(LEADERBOARD_SIZE)
+        .enumerate()
+        .map(|(i, feeder)| {
+            format!(
+                "{}. {}：{} 次",
+                i + 1,
+                crate::utils::escape_html(&feeder.name),
+                feeder.count
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
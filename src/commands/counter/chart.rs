@@ -0,0 +1,161 @@
+use png::{BitDepth, ColorType, Encoder, EncodingError};
+
+/// Width of the slot of each day in the chart, in pixels.
+const SLOT_WIDTH: usize = 44;
+
+/// Height of the tallest bar in the chart, in pixels.
+const PLOT_HEIGHT: usize = 200;
+
+const MARGIN: usize = 10;
+const TOP_MARGIN: usize = 30;
+const BOTTOM_MARGIN: usize = 24;
+
+/// Scale of the glyphs, which are 3 by 5 pixels.
+const GLYPH_SCALE: usize = 2;
+
+/// Horizontal distance between the glyphs of a text, in pixels.
+const GLYPH_ADVANCE: usize = 4 * GLYPH_SCALE;
+
+const BACKGROUND: [u8; 3] = [0xff, 0xff, 0xff];
+const GRID: [u8; 3] = [0xe0, 0xe0, 0xe0];
+const AXIS: [u8; 3] = [0x60, 0x60, 0x60];
+const BAR: [u8; 3] = [0xf0, 0x9a, 0x3e];
+const TEXT: [u8; 3] = [0x30, 0x30, 0x30];
+
+/// Render a bar chart of the given daily counts into a PNG image.
+///
+/// Each element of `counts` is the index of a day since the Unix epoch and the count of the day.
+/// Every bar is labelled by its count and the month and the day of month of its day.
+pub fn render_daily_chart(counts: &[(u64, u64)]) -> Result<Vec<u8>, EncodingError> {
+    let width = MARGIN * 2 + SLOT_WIDTH * counts.len().max(1);
+    let height = TOP_MARGIN + PLOT_HEIGHT + BOTTOM_MARGIN;
+    let mut canvas = Canvas::new(width, height);
+
+    let baseline = TOP_MARGIN + PLOT_HEIGHT;
+    for i in 1..=4 {
+        canvas.fill_rect(
+            MARGIN,
+            baseline - PLOT_HEIGHT * i / 4,
+            width - MARGIN * 2,
+            1,
+            GRID,
+        );
+    }
+    canvas.fill_rect(MARGIN, baseline, width - MARGIN * 2, 1, AXIS);
+
+    let max = counts
+        .iter()
+        .map(|(_, count)| *count)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    for (i, (day, count)) in counts.iter().enumerate() {
+        let slot = MARGIN + SLOT_WIDTH * i;
+        let center = slot + SLOT_WIDTH / 2;
+        let bar_height = (*count as usize * PLOT_HEIGHT / max as usize).max(1);
+        let bar_top = baseline - bar_height;
+        if *count > 0 {
+            canvas.fill_rect(slot + 6, bar_top, SLOT_WIDTH - 12, bar_height, BAR);
+        }
+        canvas.draw_text_centered(center, bar_top - 6 * GLYPH_SCALE - 2, &count.to_string());
+
+        let (month, day_of_month) = month_and_day(*day);
+        canvas.draw_text_centered(center, baseline + 6, &format!("{}/{}", month, day_of_month));
+    }
+
+    canvas.encode()
+}
+
+/// An RGB image.
+struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: BACKGROUND.repeat(width * height),
+        }
+    }
+
+    /// Fill the given rectangle, clipped to the image, with the given color.
+    fn fill_rect(&mut self, x: usize, y: usize, w: usize, h: usize, color: [u8; 3]) {
+        for row in y..(y + h).min(self.height) {
+            for col in x..(x + w).min(self.width) {
+                let offset = (row * self.width + col) * 3;
+                self.pixels[offset..offset + 3].copy_from_slice(&color);
+            }
+        }
+    }
+
+    /// Draw the given text horizontally centered at `center` with its top at `y`.
+    ///
+    /// Only digits and `/` are supported; other characters are drawn as spaces.
+    fn draw_text_centered(&mut self, center: usize, y: usize, text: &str) {
+        let text_width = text.chars().count() * GLYPH_ADVANCE - GLYPH_SCALE;
+        let mut x = center.saturating_sub(text_width / 2);
+        for c in text.chars() {
+            if let Some(glyph) = glyph_of(c) {
+                for (row, bits) in glyph.iter().enumerate() {
+                    for col in 0..3 {
+                        if bits & (0b100 >> col) != 0 {
+                            self.fill_rect(
+                                x + col * GLYPH_SCALE,
+                                y + row * GLYPH_SCALE,
+                                GLYPH_SCALE,
+                                GLYPH_SCALE,
+                                TEXT,
+                            );
+                        }
+                    }
+                }
+            }
+            x += GLYPH_ADVANCE;
+        }
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, EncodingError> {
+        let mut data = Vec::new();
+        let mut encoder = Encoder::new(&mut data, self.width as u32, self.height as u32);
+        encoder.set_color(ColorType::Rgb);
+        encoder.set_depth(BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.pixels)?;
+        Ok(data)
+    }
+}
+
+/// Get the glyph of the given character, as the bits of its rows from top to bottom.
+fn glyph_of(c: char) -> Option<[u8; 5]> {
+    let glyph = match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        _ => return None,
+    };
+    Some(glyph)
+}
+
+/// Get the month and the day of month of the given day since the Unix epoch.
+fn month_and_day(day: u64) -> (u64, u64) {
+    // See http://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+    let z = day + 719468;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day_of_month = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (month, day_of_month)
+}
@@ -0,0 +1,29 @@
+use crate::config::Milestones;
+
+impl Milestones {
+    /// Determine whether the given counter value is a milestone.
+    pub fn is_reached(&self, count: u64) -> bool {
+        (self.every > 0 && count.is_multiple_of(self.every))
+            || (self.powers_of_ten && is_power_of_ten(count))
+            || (self.palindrome_min_digits > 0
+                && is_long_palindrome(count, self.palindrome_min_digits))
+    }
+}
+
+/// Determine whether the given value is a power of ten no less than 10.
+fn is_power_of_ten(mut value: u64) -> bool {
+    if value < 10 {
+        return false;
+    }
+    while value.is_multiple_of(10) {
+        value /= 10;
+    }
+    value == 1
+}
+
+/// Determine whether the given value is a palindrome in decimal with at least the given number of
+/// digits.
+fn is_long_palindrome(value: u64, min_digits: u32) -> bool {
+    let digits = value.to_string().into_bytes();
+    digits.len() >= min_digits as usize && digits.iter().eq(digits.iter().rev())
+}
@@ -1,89 +1,618 @@
+mod emoji;
+mod order;
+
+use std::collections::HashMap;
 use std::error::Error;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use async_trait::async_trait;
-use teloxide::adaptors::AutoSend;
+use teloxide::adaptors::{AutoSend, DefaultParseMode};
 use teloxide::dispatching::UpdateWithCx;
-use teloxide::types::{Message, User};
+use teloxide::payloads::{AnswerCallbackQuerySetters, EditMessageTextSetters, SendMessageSetters};
+use teloxide::requests::Requester;
+use teloxide::types::{
+    CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup, Message, MessageEntityKind, User,
+};
 use teloxide::Bot;
 
-use crate::commands::{Command, CommandHandler};
-use crate::config::Config;
+use crate::commands::drink::order::DrinkOrder;
+use crate::commands::{Command, CommandHandler, CommandSpec};
+use crate::config::{Config, Drink, DrinkEmojis, DrinkModifiers};
+use crate::services::ledger::LedgerService;
+use crate::services::members::MemberService;
+use crate::services::sanitizer::Sanitizer;
+use crate::services::storage::Storage;
+
+/// Handler of the `/producedrink`, the `/round`, the `/drinkstats` and the `/owe` commands and
+/// the commands on the drink menu.
+pub struct DrinkCommandHandler {
+    /// The drinks on the drink menu, keyed by their commands and aliases.
+    menu: HashMap<String, Arc<Drink>>,
+    modifiers: DrinkModifiers,
+    emojis: DrinkEmojis,
+    sanitizer: Sanitizer,
+    ledger: LedgerService,
+    members: MemberService,
+    round_active_hours: u64,
+
+    /// Poured drinks waiting for the recipients to respond, keyed by the chat ID and the message
+    /// ID of the message announcing the drinks.
+    pending: Mutex<HashMap<(i64, i32), PendingDrink>>,
+}
+
+/// Callback data of the button accepting a poured drink.
+const ACCEPT_CALLBACK_DATA: &str = "drink:accept";
+
+/// Callback data of the button declining a poured drink.
+const DECLINE_CALLBACK_DATA: &str = "drink:decline";
+
+/// Seconds after which a poured drink no longer waits for the recipients to respond.
+const PENDING_DRINK_TIMEOUT: u64 = 24 * 60 * 60;
+
+/// Drinks poured for some users waiting for them to accept or decline.
+struct PendingDrink {
+    /// ID of the user who poured the drinks.
+    from: i64,
+
+    /// Text of the message announcing the drinks, including the responses so far.
+    text: String,
 
-/// Handler of the `/milktea`, the `/cappuccino` and the `producedrink` commands.
-#[derive(Clone, Debug, Default)]
-pub struct DrinkCommandHandler;
+    /// Name of the drink, including the modifiers.
+    drink_name: String,
+
+    /// Emoji of the drink.
+    drink_emoji: String,
+
+    /// The recipients who have not responded yet.
+    recipients: Vec<User>,
+
+    /// When the drinks were poured, in seconds since the Unix epoch.
+    time: u64,
+}
 
 impl DrinkCommandHandler {
+    /// Parse a drink order from the given arguments and pour the drink.
+    ///
+    /// `drink` is the drink on the drink menu being ordered, or `None` if the arguments start with
+    /// the name of the drink.
+    async fn order_drinks(
+        &self,
+        cx: UpdateWithCx<AutoSend<DefaultParseMode<Bot>>, Message>,
+        drink: Option<&Drink>,
+        args: &str,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let order = match self.sanitizer.sanitize(args) {
+            Ok(args) => DrinkOrder::parse(drink.map(|d| d.name.as_str()), &args, &self.modifiers)
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        match order {
+            Ok(order) => {
+                let emoji = drink.map(|d| d.emoji.as_str()).unwrap_or_default();
+                self.give_drinks(cx, &order, emoji).await
+            }
+            Err(e) => {
+                cx.answer(crate::utils::escape_html(&e)).await?;
+                Ok(())
+            }
+        }
+    }
+
     async fn give_drinks(
-        cx: UpdateWithCx<AutoSend<Bot>, Message>,
-        drink_name: &str,
+        &self,
+        cx: UpdateWithCx<AutoSend<DefaultParseMode<Bot>>, Message>,
+        order: &DrinkOrder,
         drink_emoji: &str,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
         let from = match crate::utils::get_message_sender(&cx.update) {
             Some(user) => user,
             None => return Ok(()),
         };
-        let to = crate::utils::get_replied_message(&cx.update)
-            .and_then(crate::utils::get_message_sender);
-        let response = Self::format_give_drink_message(from, to, drink_name, drink_emoji);
+
+        let recipients = self.find_recipients(&cx.update);
+        if recipients.users.is_empty() && !recipients.unknown.is_empty() {
+            cx.answer(Self::format_unknown_users_message(&recipients.unknown))
+                .await?;
+            return Ok(());
+        }
+
+        let to: Vec<_> = recipients
+            .users
+            .iter()
+            .filter(|u| u.id != from.id)
+            .collect();
+        self.record_drinks(cx.update.chat_id(), from, &to);
+
+        let drink_name = crate::utils::escape_html(&order.to_string());
+        let drink_emoji = crate::utils::escape_html(self.choose_emoji(order, drink_emoji));
+        let mut response = Self::format_give_drink_message(from, &to, &drink_name, &drink_emoji);
+        if !recipients.unknown.is_empty() {
+            response.push('\n');
+            response.push_str(&Self::format_unknown_users_message(&recipients.unknown));
+        }
+        if to.is_empty() {
+            cx.answer(response).await?;
+            return Ok(());
+        }
+
+        let sent = cx
+            .answer(response.clone())
+            .reply_markup(Self::response_keyboard())
+            .await?;
+        let time = crate::utils::get_unix_timestamp();
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, drink| drink.time + PENDING_DRINK_TIMEOUT > time);
+        pending.insert(
+            (sent.chat_id(), sent.id),
+            PendingDrink {
+                from: from.id,
+                text: response,
+                drink_name,
+                drink_emoji,
+                recipients: to.into_iter().cloned().collect(),
+                time,
+            },
+        );
+        Ok(())
+    }
+
+    /// Record the response of a recipient of a poured drink and update the message announcing the
+    /// drink.
+    async fn respond_drink(
+        &self,
+        cx: UpdateWithCx<AutoSend<DefaultParseMode<Bot>>, CallbackQuery>,
+        accepted: bool,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let query = &cx.update;
+        let message = match &query.message {
+            Some(message) => message,
+            None => return Ok(()),
+        };
+        let key = (message.chat_id(), message.id);
+        let user_mention = crate::utils::get_user_mention(&query.from);
+
+        let result = {
+            let mut pending = self.pending.lock().unwrap();
+            match pending.get_mut(&key) {
+                None => Err("这杯饮品已经凉了"),
+                Some(drink) => match drink.recipients.iter().position(|u| u.id == query.from.id) {
+                    None => Err("这杯不是给你的哦"),
+                    Some(idx) => {
+                        drink.recipients.remove(idx);
+                        let line = if accepted {
+                            format!(
+                                "{} 喝下了{} {}",
+                                user_mention, drink.drink_name, drink.drink_emoji
+                            )
+                        } else {
+                            format!("{} 婉拒了{}", user_mention, drink.drink_name)
+                        };
+                        drink.text.push('\n');
+                        drink.text.push_str(line.trim_end());
+
+                        let text = drink.text.clone();
+                        let from = drink.from;
+                        let done = drink.recipients.is_empty();
+                        if done {
+                            pending.remove(&key);
+                        }
+                        Ok((text, from, done))
+                    }
+                },
+            }
+        };
+
+        let (text, from, done) = match result {
+            Ok(result) => result,
+            Err(reason) => {
+                cx.requester
+                    .answer_callback_query(query.id.clone())
+                    .text(reason)
+                    .await?;
+                return Ok(());
+            }
+        };
+
+        let user_name = crate::utils::get_user_display_name(&query.from);
+        self.ledger
+            .record_response(key.0, from, (query.from.id, &user_name), accepted);
+
+        // Editing the message without a keyboard removes the buttons.
+        let edit = cx.requester.edit_message_text(key.0, key.1, text);
+        if done {
+            edit.await?;
+        } else {
+            edit.reply_markup(Self::response_keyboard()).await?;
+        }
+
+        let answer = if accepted { "干杯！" } else { "已婉拒" };
+        cx.requester
+            .answer_callback_query(query.id.clone())
+            .text(answer)
+            .await?;
+        Ok(())
+    }
+
+    /// Get the keyboard with which the recipients respond to poured drinks.
+    fn response_keyboard() -> InlineKeyboardMarkup {
+        InlineKeyboardMarkup::new(vec![vec![
+            InlineKeyboardButton::callback(
+                String::from("接受"),
+                String::from(ACCEPT_CALLBACK_DATA),
+            ),
+            InlineKeyboardButton::callback(
+                String::from("婉拒"),
+                String::from(DECLINE_CALLBACK_DATA),
+            ),
+        ]])
+    }
+
+    /// Pour a drink for every user who sent messages in the chat recently.
+    async fn give_round(
+        &self,
+        cx: UpdateWithCx<AutoSend<DefaultParseMode<Bot>>, Message>,
+        args: &str,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let from = match crate::utils::get_message_sender(&cx.update) {
+            Some(user) => user,
+            None => return Ok(()),
+        };
+
+        let order = match self.sanitizer.sanitize(args) {
+            Ok(args) => DrinkOrder::parse(None, &args, &self.modifiers).map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        let order = match order {
+            Ok(order) => order,
+            Err(e) => {
+                cx.answer(crate::utils::escape_html(&e)).await?;
+                return Ok(());
+            }
+        };
+
+        let since = crate::utils::get_unix_timestamp()
+            .saturating_sub(self.round_active_hours.saturating_mul(3600));
+        let to: Vec<_> = self
+            .members
+            .active_members(cx.update.chat_id(), since)
+            .into_iter()
+            .filter(|u| u.id != from.id && !u.is_bot)
+            .collect();
+        if to.is_empty() {
+            cx.answer(format!(
+                "最近 {} 小时内没有其他群友在本群发言（如果有，请让机器人的维护者在 BotFather 中用 \
+                 /setprivacy 关闭隐私模式）",
+                self.round_active_hours
+            ))
+            .await?;
+            return Ok(());
+        }
+
+        let to: Vec<_> = to.iter().collect();
+        self.record_drinks(cx.update.chat_id(), from, &to);
+
+        let drink_emoji = self
+            .menu
+            .values()
+            .find(|d| d.name == order.name)
+            .map(|d| d.emoji.as_str())
+            .unwrap_or_default();
+        let header = format!(
+            "{} 请大家喝{}！{}\n为最近 {} 小时内发言的 {} 位群友各倒了一杯：\n",
+            crate::utils::get_user_mention(from),
+            crate::utils::escape_html(&order.to_string()),
+            crate::utils::escape_html(self.choose_emoji(&order, drink_emoji)),
+            self.round_active_hours,
+            to.len()
+        );
+        let names = to.iter().map(|u| crate::utils::get_user_mention(u));
+        let limit = crate::utils::MAX_MESSAGE_LENGTH.saturating_sub(header.chars().count());
+        for (i, chunk) in crate::utils::join_into_chunks(names, "、", limit)
+            .into_iter()
+            .enumerate()
+        {
+            if i == 0 {
+                cx.answer(format!("{}{}", header, chunk)).await?;
+            } else {
+                cx.answer(chunk).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Record the drinks poured from a user to the given recipients in the ledger.
+    fn record_drinks(&self, chat_id: i64, from: &User, to: &[&User]) {
+        if to.is_empty() {
+            return;
+        }
+
+        let to_names: Vec<_> = to
+            .iter()
+            .map(|u| (u.id, crate::utils::get_user_display_name(u)))
+            .collect();
+        let to_names: Vec<_> = to_names
+            .iter()
+            .map(|(id, name)| (*id, name.as_str()))
+            .collect();
+        self.ledger.record(
+            chat_id,
+            (from.id, &crate::utils::get_user_display_name(from)),
+            &to_names,
+        );
+    }
+
+    /// Choose the emoji of the ordered drink.
+    ///
+    /// The given emoji is chosen unless it is empty, in which case the emoji is inferred from the
+    /// name of the drink.
+    fn choose_emoji<'a>(&'a self, order: &DrinkOrder, drink_emoji: &'a str) -> &'a str {
+        if drink_emoji.is_empty() {
+            self.emojis.infer(&order.name)
+        } else {
+            drink_emoji
+        }
+    }
+
+    /// Find the recipients of a drink from the given command message.
+    ///
+    /// The recipients include the sender of the replied message and the users mentioned in the
+    /// command message, in order of appearance. Users mentioned by their usernames are resolved
+    /// among the users seen in the chat.
+    fn find_recipients(&self, message: &Message) -> Recipients {
+        let mut recipients = Recipients::default();
+        if let Some(user) =
+            crate::utils::get_replied_message(message).and_then(crate::utils::get_message_sender)
+        {
+            recipients.push(user.clone());
+        }
+
+        let text = message.text().unwrap_or_default();
+        for entity in message.entities().unwrap_or_default() {
+            match &entity.kind {
+                MessageEntityKind::TextMention { user } => recipients.push(user.clone()),
+                MessageEntityKind::Mention => {
+                    let mention = match crate::utils::get_entity_text(text, entity) {
+                        Some(mention) => mention,
+                        None => continue,
+                    };
+                    let username = mention.trim_start_matches('@');
+                    match self.members.find_by_username(message.chat_id(), username) {
+                        Some(user) => recipients.push(user),
+                        None => recipients.unknown.push(mention.to_owned()),
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        recipients
+    }
+
+    /// Get the arguments of the given command message, excluding the mentioned users.
+    fn get_arguments(message: &Message) -> Option<String> {
+        let text = crate::utils::get_text_without_mentions(message)?;
+        Some(
+            text.split_whitespace()
+                .skip(1)
+                .collect::<Vec<_>>()
+                .join(" "),
+        )
+    }
+
+    fn format_unknown_users_message(unknown: &[String]) -> String {
+        format!(
+            "找不到用户：{}（只能找到在本群发过言的用户）",
+            crate::utils::escape_html(&unknown.join("、"))
+        )
+    }
+
+    async fn show_stats(
+        &self,
+        cx: UpdateWithCx<AutoSend<DefaultParseMode<Bot>>, Message>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let ledger = self.ledger.get(cx.update.chat_id());
+        let mut stats: Vec<_> = ledger.stats().into_iter().collect();
+        if stats.is_empty() {
+            cx.answer("本群还没有人请过饮品").await?;
+            return Ok(());
+        }
+
+        stats.sort_by(|(_, a), (_, b)| {
+            b.given
+                .cmp(&a.given)
+                .then_with(|| b.received.cmp(&a.received))
+        });
+        let mut response = String::from("饮品统计：");
+        for (user_id, s) in stats {
+            response.push_str(&format!(
+                "\n{}：请客 {} 杯，被请 {} 杯（喝下 {} 杯，婉拒 {} 杯）",
+                crate::utils::escape_html(ledger.name_of(user_id)),
+                s.given,
+                s.received,
+                s.responses.accepted,
+                s.responses.declined
+            ));
+        }
+        cx.answer(response).await?;
+        Ok(())
+    }
+
+    async fn show_debts(
+        &self,
+        cx: UpdateWithCx<AutoSend<DefaultParseMode<Bot>>, Message>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let ledger = self.ledger.get(cx.update.chat_id());
+        let mut debts = ledger.debts();
+        if debts.is_empty() {
+            cx.answer("本群互不相欠").await?;
+            return Ok(());
+        }
+
+        debts.sort_by_key(|debt| std::cmp::Reverse(debt.drinks));
+        let mut response = String::from("欠款统计：");
+        for debt in debts {
+            response.push_str(&format!(
+                "\n{} 欠 {} {} 杯",
+                crate::utils::escape_html(ledger.name_of(debt.debtor)),
+                crate::utils::escape_html(ledger.name_of(debt.creditor)),
+                debt.drinks
+            ));
+        }
         cx.answer(response).await?;
         Ok(())
     }
 
+    /// Format the HTML message announcing the drinks poured.
+    ///
+    /// `drink_name` and `drink_emoji` must have been escaped.
     fn format_give_drink_message(
         from: &User,
-        to: Option<&User>,
+        to: &[&User],
         drink_name: &str,
         drink_emoji: &str,
     ) -> String {
-        let mut to = to;
-        if let Some(u) = &to {
-            if from.id == u.id {
-                to = None;
-            }
-        }
+        let from_name = crate::utils::get_user_mention(from);
+        let to_names: Vec<_> = to
+            .iter()
+            .map(|u| crate::utils::get_user_mention(u))
+            .collect();
 
-        let from_name = crate::utils::get_user_display_name(from);
-
-        if let Some(u) = &to {
-            let to_name = crate::utils::get_user_display_name(u);
-            format!(
-                "{} 给 {} 倒了一杯{}！{}",
-                from_name, to_name, drink_name, drink_emoji
-            )
-        } else {
-            format!(
+        match to_names.len() {
+            0 => format!(
                 "{} 给自己倒了一杯{}！{}",
                 from_name, drink_name, drink_emoji
-            )
+            ),
+            1 => format!(
+                "{} 给 {} 倒了一杯{}！{}",
+                from_name, to_names[0], drink_name, drink_emoji
+            ),
+            _ => format!(
+                "{} 给 {} 各倒了一杯{}！{}",
+                from_name,
+                to_names.join("、"),
+                drink_name,
+                drink_emoji
+            ),
+        }
+    }
+}
+
+/// Recipients of a drink found in a command message.
+#[derive(Default)]
+struct Recipients {
+    /// The users found, without duplicates.
+    users: Vec<User>,
+
+    /// The mentions that cannot be resolved to any user.
+    unknown: Vec<String>,
+}
+
+impl Recipients {
+    fn push(&mut self, user: User) {
+        if self.users.iter().all(|u| u.id != user.id) {
+            self.users.push(user);
         }
     }
 }
 
 #[async_trait]
 impl CommandHandler for DrinkCommandHandler {
-    fn new(_config: &Config) -> Result<Self, Box<dyn Error>> {
-        Ok(Self)
+    fn new(config: &Config, storage: &Arc<dyn Storage>) -> Result<Self, Box<dyn Error>> {
+        let mut menu = HashMap::new();
+        for drink in &config.drinks {
+            let drink = Arc::new(drink.clone());
+            for cmd in std::iter::once(&drink.command).chain(&drink.aliases) {
+                menu.insert(cmd.to_lowercase(), drink.clone());
+            }
+        }
+        Ok(Self {
+            menu,
+            modifiers: config.drink_modifiers.clone(),
+            emojis: config.drink_emojis.clone(),
+            sanitizer: Sanitizer::new(&config.sanitizer),
+            ledger: LedgerService::new(storage.clone())?,
+            members: MemberService::new(storage.clone(), config.round_active_hours)?,
+            round_active_hours: config.round_active_hours,
+            pending: Mutex::new(HashMap::new()),
+        })
     }
 
-    fn accept(self: Arc<Self>, cmd: &Command) -> bool {
-        match cmd {
-            Command::Milktea | Command::Cappuccino | Command::ProduceDrink { .. } => true,
-            _ => false,
+    fn observe(self: Arc<Self>, message: &Message) {
+        if let Some(user) = crate::utils::get_message_sender(message) {
+            self.members.mark_active(message.chat_id(), user.id);
         }
+
+        let mut users = Vec::new();
+        users.extend(crate::utils::get_message_sender(message));
+        users.extend(
+            crate::utils::get_replied_message(message).and_then(crate::utils::get_message_sender),
+        );
+        for entity in message.entities().unwrap_or_default() {
+            if let MessageEntityKind::TextMention { user } = &entity.kind {
+                users.push(user);
+            }
+        }
+        self.members.record(message.chat_id(), users);
+    }
+
+    fn shutdown(self: Arc<Self>) {
+        self.ledger.flush();
+        self.members.flush();
+    }
+
+    fn commands(config: &Config) -> Vec<CommandSpec> {
+        let mut commands = vec![
+            CommandSpec::builtin("producedrink", "饮品", "线上生产饮品"),
+            CommandSpec::builtin("round", "饮品", "给最近发言的所有群友倒一杯饮品"),
+            CommandSpec::builtin("drinkstats", "", "显示本群的饮品统计"),
+            CommandSpec::builtin("owe", "", "显示本群成员之间的欠款"),
+        ];
+        for drink in &config.drinks {
+            commands.push(CommandSpec::custom(&drink.command, &drink.description));
+            // Aliases are routed to the drink but not listed.
+            commands.extend(
+                drink
+                    .aliases
+                    .iter()
+                    .map(|alias| CommandSpec::custom(alias, &drink.description).hidden()),
+            );
+        }
+        commands
     }
 
     async fn handle(
         self: Arc<Self>,
-        ctx: UpdateWithCx<AutoSend<Bot>, Message>,
+        ctx: UpdateWithCx<AutoSend<DefaultParseMode<Bot>>, Message>,
         cmd: Command,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        match cmd {
-            Command::Milktea => Self::give_drinks(ctx, "奶茶", "🧋").await,
-            Command::Cappuccino => Self::give_drinks(ctx, "卡布奇诺", "☕️").await,
-            Command::ProduceDrink { drink_name } => Self::give_drinks(ctx, &drink_name, "").await,
-            _ => unreachable!(),
+        match cmd.name.as_str() {
+            "producedrink" => {
+                let args = Self::get_arguments(&ctx.update).unwrap_or(cmd.args);
+                self.order_drinks(ctx, None, &args).await
+            }
+            "round" => {
+                let args = Self::get_arguments(&ctx.update).unwrap_or(cmd.args);
+                self.give_round(ctx, &args).await
+            }
+            "drinkstats" => self.show_stats(ctx).await,
+            "owe" => self.show_debts(ctx).await,
+            name => {
+                let drink = self.menu[name].clone();
+                let args = Self::get_arguments(&ctx.update).unwrap_or_default();
+                self.order_drinks(ctx, Some(&drink), &args).await
+            }
         }
     }
+
+    fn accept_callback(self: Arc<Self>, data: &str) -> bool {
+        data == ACCEPT_CALLBACK_DATA || data == DECLINE_CALLBACK_DATA
+    }
+
+    async fn handle_callback(
+        self: Arc<Self>,
+        ctx: UpdateWithCx<AutoSend<DefaultParseMode<Bot>>, CallbackQuery>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let accepted = ctx.update.data.as_deref() == Some(ACCEPT_CALLBACK_DATA);
+        self.respond_drink(ctx, accepted).await
+    }
 }
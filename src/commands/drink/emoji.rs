@@ -0,0 +1,21 @@
+use crate::config::DrinkEmojis;
+
+impl DrinkEmojis {
+    /// Infer the emoji of a drink from its name.
+    ///
+    /// The emoji of the longest keyword contained in the name is chosen. The fallback emoji is
+    /// returned if the name contains no keyword.
+    pub fn infer(&self, drink_name: &str) -> &str {
+        self.keywords
+            .iter()
+            .filter(|(keyword, _)| drink_name.contains(keyword.as_str()))
+            .max_by(|(a, _), (b, _)| {
+                a.chars()
+                    .count()
+                    .cmp(&b.chars().count())
+                    .then_with(|| b.cmp(a))
+            })
+            .map(|(_, emoji)| emoji.as_str())
+            .unwrap_or(&self.fallback)
+    }
+}
@@ -0,0 +1,187 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use crate::config::DrinkModifiers;
+
+/// A drink order with customizations.
+#[derive(Clone, Debug, Default)]
+pub struct DrinkOrder {
+    /// Name of the drink.
+    pub name: String,
+
+    /// Size of the drink, e.g. `大杯`.
+    pub size: Option<String>,
+
+    /// Amount of ice in the drink, e.g. `少冰`.
+    pub ice: Option<String>,
+
+    /// Amount of sugar in the drink, e.g. `半糖`.
+    pub sugar: Option<String>,
+}
+
+impl DrinkOrder {
+    /// Parse a drink order from the arguments of a drink command.
+    ///
+    /// If `name` is `None`, the arguments start with the name of the drink, which spans all the
+    /// words before the first modifier. All the remaining words must be modifiers in the given
+    /// vocabularies, and at most one modifier of each kind may be given.
+    pub fn parse(
+        name: Option<&str>,
+        args: &str,
+        modifiers: &DrinkModifiers,
+    ) -> Result<Self, OrderError> {
+        let mut words = args.split_whitespace().peekable();
+
+        let name = match name {
+            Some(name) => name.to_owned(),
+            None => {
+                let mut name_words = Vec::new();
+                while let Some(word) = words.next_if(|w| modifiers.kind_of(w).is_none()) {
+                    name_words.push(word);
+                }
+                if name_words.is_empty() {
+                    return Err(OrderError::MissingName);
+                }
+                name_words.join(" ")
+            }
+        };
+
+        let mut order = Self {
+            name,
+            ..Self::default()
+        };
+        for word in words {
+            let slot = match modifiers.kind_of(word) {
+                Some(ModifierKind::Size) => &mut order.size,
+                Some(ModifierKind::Ice) => &mut order.ice,
+                Some(ModifierKind::Sugar) => &mut order.sugar,
+                None => return Err(OrderError::UnknownModifier(word.to_owned())),
+            };
+            if let Some(previous) = slot {
+                return Err(OrderError::ConflictingModifiers(
+                    previous.clone(),
+                    word.to_owned(),
+                ));
+            }
+            *slot = Some(word.to_owned());
+        }
+
+        Ok(order)
+    }
+}
+
+impl Display for DrinkOrder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for modifier in [&self.size, &self.ice, &self.sugar].into_iter().flatten() {
+            f.write_str(modifier)?;
+        }
+        f.write_str(&self.name)
+    }
+}
+
+/// Kind of a drink modifier.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ModifierKind {
+    Size,
+    Ice,
+    Sugar,
+}
+
+impl DrinkModifiers {
+    /// Get the kind of the given modifier, or `None` if it is not in any vocabulary.
+    pub fn kind_of(&self, word: &str) -> Option<ModifierKind> {
+        if self.sizes.iter().any(|m| m == word) {
+            Some(ModifierKind::Size)
+        } else if self.ices.iter().any(|m| m == word) {
+            Some(ModifierKind::Ice)
+        } else if self.sugars.iter().any(|m| m == word) {
+            Some(ModifierKind::Sugar)
+        } else {
+            None
+        }
+    }
+}
+
+/// Error in a drink order.
+#[derive(Clone, Debug)]
+pub enum OrderError {
+    /// The name of the drink is not given.
+    MissingName,
+
+    /// A word is not a modifier in any vocabulary.
+    UnknownModifier(String),
+
+    /// Two modifiers of the same kind are given.
+    ConflictingModifiers(String, String),
+}
+
+impl Display for OrderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingName => write!(f, "请指定饮品名称，例如：/producedrink 奶茶 少冰 半糖"),
+            Self::UnknownModifier(word) => write!(f, "无法识别的饮品选项：{}", word),
+            Self::ConflictingModifiers(a, b) => write!(f, "饮品选项冲突：{} 和 {}", a, b),
+        }
+    }
+}
+
+impl Error for OrderError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn modifiers() -> DrinkModifiers {
+        let words = |words: &[&str]| words.iter().map(|w| w.to_string()).collect();
+        DrinkModifiers {
+            sizes: words(&["中杯", "大杯"]),
+            ices: words(&["去冰", "少冰"]),
+            sugars: words(&["无糖", "半糖"]),
+        }
+    }
+
+    #[test]
+    fn multi_word_name_is_followed_by_modifiers() {
+        let order = DrinkOrder::parse(None, "芝士 奶盖 绿茶 半糖 大杯", &modifiers()).unwrap();
+        assert_eq!(order.name, "芝士 奶盖 绿茶");
+        assert_eq!(order.size.as_deref(), Some("大杯"));
+        assert_eq!(order.ice, None);
+        assert_eq!(order.sugar.as_deref(), Some("半糖"));
+    }
+
+    #[test]
+    fn given_name_takes_only_modifiers() {
+        let order = DrinkOrder::parse(Some("奶茶"), "少冰", &modifiers()).unwrap();
+        assert_eq!(order.name, "奶茶");
+        assert_eq!(order.ice.as_deref(), Some("少冰"));
+    }
+
+    #[test]
+    fn missing_name_is_rejected() {
+        for args in ["", "  ", "大杯 少冰"] {
+            let result = DrinkOrder::parse(None, args, &modifiers());
+            assert!(matches!(result, Err(OrderError::MissingName)), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn unknown_modifier_is_rejected() {
+        let result = DrinkOrder::parse(None, "奶茶 少冰 珍珠", &modifiers());
+        assert!(matches!(result, Err(OrderError::UnknownModifier(w)) if w == "珍珠"));
+    }
+
+    #[test]
+    fn conflicting_modifiers_are_rejected() {
+        let result = DrinkOrder::parse(None, "奶茶 去冰 半糖 少冰", &modifiers());
+        assert!(matches!(
+            result,
+            Err(OrderError::ConflictingModifiers(a, b)) if a == "去冰" && b == "少冰"
+        ));
+    }
+
+    #[test]
+    fn display_puts_size_ice_and_sugar_before_name() {
+        let order = DrinkOrder::parse(None, "奶茶 半糖 少冰 大杯", &modifiers()).unwrap();
+        assert_eq!(order.to_string(), "大杯少冰半糖奶茶");
+    }
+}
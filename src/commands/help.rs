@@ -2,38 +2,56 @@ use std::error::Error;
 use std::sync::Arc;
 
 use async_trait::async_trait;
-use teloxide::adaptors::AutoSend;
+use teloxide::adaptors::{AutoSend, DefaultParseMode};
 use teloxide::dispatching::UpdateWithCx;
 use teloxide::types::Message;
-use teloxide::utils::command::BotCommand;
 use teloxide::Bot;
 
-use crate::commands::{Command, CommandHandler};
+use crate::commands::{Command, CommandHandler, CommandSpec, Visibility};
 use crate::config::Config;
+use crate::services::storage::Storage;
 
 /// Handler of the `/start` and the `/help` commands.
 #[derive(Clone, Debug, Default)]
-pub struct HelpCommandHandler;
+pub struct HelpCommandHandler {
+    help_text: String,
+}
 
 #[async_trait]
 impl CommandHandler for HelpCommandHandler {
-    fn new(_config: &Config) -> Result<Self, Box<dyn Error>> {
-        Ok(Self)
+    fn new(config: &Config, _storage: &Arc<dyn Storage>) -> Result<Self, Box<dyn Error>> {
+        let mut help_text = String::from("支持的命令：\n");
+        for spec in crate::commands::all_commands(config) {
+            if spec.visibility != Visibility::Public {
+                continue;
+            }
+            if spec.args.is_empty() {
+                help_text.push_str(&format!("/{} - {}\n", spec.name, spec.description));
+            } else {
+                help_text.push_str(&format!(
+                    "/{} {} - {}\n",
+                    spec.name, spec.args, spec.description
+                ));
+            }
+        }
+        Ok(Self {
+            help_text: crate::utils::escape_html(&help_text),
+        })
     }
 
-    fn accept(self: Arc<Self>, cmd: &Command) -> bool {
-        match cmd {
-            Command::Help | Command::Start => true,
-            _ => false,
-        }
+    fn commands(_config: &Config) -> Vec<CommandSpec> {
+        vec![
+            CommandSpec::builtin("start", "", "开始交互并显示帮助信息"),
+            CommandSpec::builtin("help", "", "显示帮助信息"),
+        ]
     }
 
     async fn handle(
         self: Arc<Self>,
-        ctx: UpdateWithCx<AutoSend<Bot>, Message>,
+        ctx: UpdateWithCx<AutoSend<DefaultParseMode<Bot>>, Message>,
         _cmd: Command,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let answer_result = ctx.answer(Command::descriptions()).await;
+        let answer_result = ctx.answer(self.help_text.clone()).await;
         if let Err(e) = answer_result {
             return Err(Box::new(e));
         }
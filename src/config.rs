@@ -1,18 +1,222 @@
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
 
 use serde::{Deserialize, Serialize};
 
+mod validation;
+
 /// Application configuration.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Config {
-    /// The list of canteens.
-    pub canteens: Vec<Canteen>,
+    /// Named sets of canteens, keyed by the name of the set.
+    #[serde(default)]
+    pub canteen_sets: BTreeMap<String, Vec<Canteen>>,
+
+    /// Name of the canteen set used by chats that have not chosen one.
+    #[serde(default = "default_canteen_set")]
+    pub default_canteen_set: String,
+
+    /// The single list of canteens of earlier versions, see `Config::migrate`.
+    #[serde(default, skip_serializing)]
+    canteens: Vec<Canteen>,
+
+    /// The drink menu.
+    #[serde(default)]
+    pub drinks: Vec<Drink>,
+
+    /// Vocabularies of the modifiers accepted in drink orders.
+    #[serde(default)]
+    pub drink_modifiers: DrinkModifiers,
+
+    /// Emojis inferred for drinks that do not specify one.
+    #[serde(default)]
+    pub drink_emojis: DrinkEmojis,
+
+    /// Users who sent messages within this number of hours receive a drink in a round.
+    #[serde(default = "default_round_active_hours")]
+    pub round_active_hours: u64,
+
+    /// Limits on the free-text arguments of commands.
+    #[serde(default)]
+    pub sanitizer: SanitizerConfig,
+
+    /// The counters increased by commands, e.g. the meow counter.
+    #[serde(default)]
+    pub counters: Vec<Counter>,
+
+    /// Path to the backing file of the meow counter of earlier versions, see `Config::migrate`.
+    #[serde(default, skip_serializing)]
+    meow_counter_file: Option<PathBuf>,
 
-    /// Path to the backing file of the meow counter.
-    pub meow_counter_file: PathBuf,
+    /// Path to the backing file of the twd2 counter of earlier versions, see `Config::migrate`.
+    #[serde(default, skip_serializing)]
+    twd2_counter_file: Option<PathBuf>,
 
-    /// Path to the backing file of the twd2 counter.
-    pub twd2_counter_file: PathBuf,
+    /// Configuration of the pets adopted by chats.
+    #[serde(default)]
+    pub adoption: Adoption,
+
+    /// The storage backend holding the state of the bot, which defaults to files in the working
+    /// directory as in earlier versions.
+    #[serde(default = "default_storage")]
+    pub storage: StorageConfig,
+
+    /// IDs of the users allowed to manage the counters.
+    #[serde(default)]
+    pub admins: Vec<i64>,
+
+    /// Offset of the local time of the users from UTC, in hours, which decides where days and
+    /// weeks start in the statistics and the leaderboards.
+    #[serde(default = "default_utc_offset_hours")]
+    pub utc_offset_hours: i64,
+}
+
+impl Config {
+    /// Move the settings written by earlier versions into their current places.
+    ///
+    /// The top-level `canteens` list becomes the canteen set named by `default_canteen_set`,
+    /// unless a set of that name is also given.
+    ///
+    /// The `meow_counter_file` and `twd2_counter_file` keys become counters with the commands of
+    /// earlier versions, keyed by the names of the files. This requires the files to lie in the
+    /// directory of the `file` storage backend; otherwise the keys are kept and rejected by
+    /// `Config::validate`.
+    pub fn migrate(&mut self) {
+        if let Some(path) = self.meow_counter_file.take() {
+            self.meow_counter_file = self.migrate_counter(path, legacy_meow_counter);
+        }
+        if let Some(path) = self.twd2_counter_file.take() {
+            self.twd2_counter_file = self.migrate_counter(path, legacy_twd2_counter);
+        }
+
+        if self.canteens.is_empty() {
+            return;
+        }
+        let canteens = std::mem::take(&mut self.canteens);
+        if self.canteen_sets.contains_key(&self.default_canteen_set) {
+            log::warn!(
+                "Ignoring the legacy canteens since canteen set \"{}\" is also given",
+                self.default_canteen_set
+            );
+            return;
+        }
+        self.canteen_sets
+            .insert(self.default_canteen_set.clone(), canteens);
+    }
+
+    /// Turn the backing file of a counter of earlier versions into the counter created by
+    /// `counter` from the key of the counter.
+    ///
+    /// The path is given back if the counter cannot be migrated.
+    fn migrate_counter(
+        &mut self,
+        path: PathBuf,
+        counter: fn(String) -> Counter,
+    ) -> Option<PathBuf> {
+        let dir = match &self.storage {
+            StorageConfig::File { path } => path,
+            _ => return Some(path),
+        };
+        let key = match path.file_name().and_then(|name| name.to_str()) {
+            Some(key) if validation::is_valid_counter_key(key) => key.to_owned(),
+            _ => return Some(path),
+        };
+        if !is_same_dir(path.parent().unwrap_or_else(|| Path::new("")), dir) {
+            return Some(path);
+        }
+
+        if self.counters.iter().any(|c| c.key == key) {
+            log::warn!(
+                "Ignoring the legacy counter file {} since counter \"{}\" is also given",
+                path.display(),
+                key
+            );
+        } else {
+            self.counters.push(counter(key));
+        }
+        None
+    }
+}
+
+/// Determine whether the given relative or absolute paths name the same directory, without
+/// accessing the file system.
+fn is_same_dir(a: &Path, b: &Path) -> bool {
+    fn components(path: &Path) -> impl Iterator<Item = Component<'_>> {
+        path.components().filter(|c| *c != Component::CurDir)
+    }
+    components(a).eq(components(b))
+}
+
+/// Create the meow counter of earlier versions with the given key.
+fn legacy_meow_counter(key: String) -> Counter {
+    let template = |line: &str| format!("Meow~~~\n猫咪投喂计数器：{{count}}\n{}", line);
+    legacy_counter(
+        key,
+        "猫咪",
+        vec![
+            legacy_counter_command("feedmeow", "投喂猫咪", template("呼呼喵喵zzz")),
+            legacy_counter_command(
+                "feedmeowwd40",
+                "使用 WD40 投喂猫咪",
+                template("精神喵喵！！！"),
+            ),
+        ],
+    )
+}
+
+/// Create the twd2 counter of earlier versions with the given key.
+fn legacy_twd2_counter(key: String) -> Counter {
+    legacy_counter(
+        key,
+        "二老师",
+        vec![legacy_counter_command(
+            "feedtwd2",
+            "使用酥酥万呆粒投喂二老师",
+            String::from("二老师投喂计数器：{count}\n向二老师投喂酥酥万呆粒一枚~"),
+        )],
+    )
+}
+
+/// Create a counter of earlier versions, which has nothing but its commands.
+fn legacy_counter(key: String, name: &str, commands: Vec<CounterCommand>) -> Counter {
+    Counter {
+        key,
+        name: name.to_owned(),
+        commands,
+        milestones: None,
+        pet: None,
+        cooldown: Cooldown::default(),
+        stats: None,
+        events: FeedEvents::default(),
+    }
+}
+
+fn legacy_counter_command(command: &str, description: &str, template: String) -> CounterCommand {
+    CounterCommand {
+        command: command.to_owned(),
+        description: description.to_owned(),
+        template,
+        satiety: 0,
+        energy: 0,
+    }
+}
+
+fn default_canteen_set() -> String {
+    String::from("default")
+}
+
+fn default_storage() -> StorageConfig {
+    StorageConfig::File {
+        path: PathBuf::from("."),
+    }
+}
+
+fn default_utc_offset_hours() -> i64 {
+    8
+}
+
+fn default_round_active_hours() -> u64 {
+    24
 }
 
 /// Information about a canteen.
@@ -24,3 +228,392 @@ pub struct Canteen {
     /// The weight of the canteen.
     pub weight: u64,
 }
+
+/// Information about a drink on the drink menu.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Drink {
+    /// The command that pours the drink, without the leading `/`.
+    pub command: String,
+
+    /// The name of the drink.
+    pub name: String,
+
+    /// The emoji shown after the name of the drink.
+    #[serde(default)]
+    pub emoji: String,
+
+    /// Alternative commands that pour the drink, without the leading `/`.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+
+    /// Description of the command shown in the command list.
+    pub description: String,
+}
+
+/// Vocabularies of the modifiers accepted in drink orders.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct DrinkModifiers {
+    /// Modifiers of the size of a drink, e.g. `大杯`.
+    #[serde(default)]
+    pub sizes: Vec<String>,
+
+    /// Modifiers of the amount of ice in a drink, e.g. `少冰`.
+    #[serde(default)]
+    pub ices: Vec<String>,
+
+    /// Modifiers of the amount of sugar in a drink, e.g. `半糖`.
+    #[serde(default)]
+    pub sugars: Vec<String>,
+}
+
+/// Emojis inferred for drinks from their names.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct DrinkEmojis {
+    /// Emojis keyed by the keywords in the names of the drinks, e.g. `咖啡: ☕`.
+    #[serde(default)]
+    pub keywords: BTreeMap<String, String>,
+
+    /// The emoji used when the name of a drink contains no keyword.
+    #[serde(default)]
+    pub fallback: String,
+}
+
+/// Limits on the free-text arguments of commands.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SanitizerConfig {
+    /// Maximum number of characters in a free-text argument.
+    #[serde(default = "default_max_argument_length")]
+    pub max_length: usize,
+
+    /// Words that must not appear in a free-text argument, matched case-insensitively.
+    #[serde(default)]
+    pub blocked_words: Vec<String>,
+}
+
+impl Default for SanitizerConfig {
+    fn default() -> Self {
+        Self {
+            max_length: default_max_argument_length(),
+            blocked_words: Vec::new(),
+        }
+    }
+}
+
+fn default_max_argument_length() -> usize {
+    64
+}
+
+/// The storage backend holding the state of the bot.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum StorageConfig {
+    /// Store the state in files in the given directory.
+    File { path: PathBuf },
+
+    /// Store the state in the given SQLite database file.
+    Sqlite { path: PathBuf },
+
+    /// Keep the state in memory, e.g. for tests.
+    Memory,
+}
+
+/// A counter increased by commands.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Counter {
+    /// The storage key of the counter.
+    ///
+    /// The counter is stored in the document with the key, and its increments are stored in the
+    /// log with the key followed by `.events`.
+    pub key: String,
+
+    /// The name of the counter shown in the leaderboard, e.g. the name of the fed cat. The key is
+    /// shown if no name is given.
+    #[serde(default)]
+    pub name: String,
+
+    /// The commands that increase the counter.
+    pub commands: Vec<CounterCommand>,
+
+    /// The milestones of the counter to celebrate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub milestones: Option<Milestones>,
+
+    /// The pet fed by the commands of the counter, if the counter simulates a pet.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pet: Option<Pet>,
+
+    /// The cooldown shared by the commands of the counter.
+    #[serde(default)]
+    pub cooldown: Cooldown,
+
+    /// The command showing the statistics of the counter.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<StatsCommand>,
+
+    /// The random events happening when the counter is increased.
+    #[serde(default)]
+    pub events: FeedEvents,
+}
+
+/// A command that increases a counter.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CounterCommand {
+    /// The command, without the leading `/`.
+    pub command: String,
+
+    /// Description of the command shown in the command list.
+    pub description: String,
+
+    /// Template of the response to the command.
+    ///
+    /// `{count}`, `{chat_count}` and `{user_count}` are replaced by the updated values of the
+    /// counter across all chats, in the current chat and of the user who sent the command,
+    /// respectively. `{user}` is replaced by a mention of the user who sent the command.
+    pub template: String,
+
+    /// How much the command reduces the hunger of the pet of the counter.
+    #[serde(default)]
+    pub satiety: u32,
+
+    /// How much the command increases the energy of the pet of the counter.
+    #[serde(default)]
+    pub energy: u32,
+}
+
+/// Milestones of a counter, celebrated when the global value of the counter reaches them.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Milestones {
+    /// Celebrate every multiple of this value. Zero disables such milestones.
+    #[serde(default)]
+    pub every: u64,
+
+    /// Celebrate the powers of ten, starting from 10.
+    #[serde(default)]
+    pub powers_of_ten: bool,
+
+    /// Celebrate the palindromic values with at least this many digits. Zero disables such
+    /// milestones.
+    #[serde(default)]
+    pub palindrome_min_digits: u32,
+
+    /// Template of the celebration message.
+    ///
+    /// The placeholders are the same as in `CounterCommand::template`.
+    pub template: String,
+
+    /// Whether to pin the celebration message in the chat.
+    #[serde(default)]
+    pub pin: bool,
+}
+
+/// A pet simulated by a counter.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Pet {
+    /// The command showing the state of the pet, without the leading `/`.
+    pub command: String,
+
+    /// Description of the command shown in the command list.
+    pub description: String,
+
+    #[serde(flatten)]
+    pub traits: PetTraits,
+}
+
+/// How the state of a simulated pet, whose hunger and energy range from 0 to 100, changes.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PetTraits {
+    /// How much the hunger of the pet increases per hour.
+    #[serde(default = "default_hunger_per_hour")]
+    pub hunger_per_hour: u32,
+
+    /// How much the energy of the pet decreases per hour.
+    #[serde(default = "default_energy_per_hour")]
+    pub energy_per_hour: u32,
+
+    /// How long the pet stays grumpy after it is overfed, in minutes.
+    #[serde(default = "default_grumpy_minutes")]
+    pub grumpy_minutes: u64,
+}
+
+impl Default for PetTraits {
+    fn default() -> Self {
+        Self {
+            hunger_per_hour: default_hunger_per_hour(),
+            energy_per_hour: default_energy_per_hour(),
+            grumpy_minutes: default_grumpy_minutes(),
+        }
+    }
+}
+
+/// Configuration of the pets adopted by chats.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Adoption {
+    /// How much feeding reduces the hunger of an adopted pet.
+    #[serde(default = "default_adoption_satiety")]
+    pub satiety: u32,
+
+    #[serde(flatten)]
+    pub traits: PetTraits,
+
+    /// The cooldown of feeding an adopted pet.
+    #[serde(default)]
+    pub cooldown: Cooldown,
+}
+
+impl Default for Adoption {
+    fn default() -> Self {
+        Self {
+            satiety: default_adoption_satiety(),
+            traits: PetTraits::default(),
+            cooldown: Cooldown::default(),
+        }
+    }
+}
+
+fn default_adoption_satiety() -> u32 {
+    20
+}
+
+/// Random events happening when a counter is increased.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FeedEvents {
+    /// Probability that an increment is critical and counts double.
+    #[serde(default)]
+    pub critical_chance: f64,
+
+    /// Message appended to the response to a critical increment.
+    #[serde(default = "default_critical_message")]
+    pub critical_message: String,
+
+    /// The items that may drop to the user increasing the counter.
+    #[serde(default)]
+    pub drops: Vec<LootDrop>,
+}
+
+impl Default for FeedEvents {
+    fn default() -> Self {
+        Self {
+            critical_chance: 0.0,
+            critical_message: default_critical_message(),
+            drops: Vec::new(),
+        }
+    }
+}
+
+fn default_critical_message() -> String {
+    String::from("暴击！这次算作两次")
+}
+
+/// An item that may drop when a counter is increased.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LootDrop {
+    /// Name of the item, which is added to the inventory of the user.
+    pub item: String,
+
+    /// Probability that the item drops on an increment.
+    pub chance: f64,
+
+    /// Message appended to the response when the item drops.
+    ///
+    /// `{user}` is replaced by a mention of the user who receives the item.
+    pub message: String,
+}
+
+/// A command showing the statistics of a counter.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StatsCommand {
+    /// The command, without the leading `/`.
+    pub command: String,
+
+    /// Description of the command shown in the command list.
+    pub description: String,
+}
+
+/// Cooldown of commands, limiting how often they can be used.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Cooldown {
+    /// Minimum interval between two uses by the same user, in seconds. Zero disables the limit.
+    #[serde(default)]
+    pub user_seconds: u64,
+
+    /// Minimum interval between two uses in the same chat, in seconds. Zero disables the limit.
+    #[serde(default)]
+    pub chat_seconds: u64,
+
+    /// Template of the response to a command used during the cooldown.
+    ///
+    /// `{seconds}` is replaced by the number of seconds until the cooldown ends.
+    #[serde(default = "default_cooldown_message")]
+    pub message: String,
+}
+
+impl Default for Cooldown {
+    fn default() -> Self {
+        Self {
+            user_seconds: 0,
+            chat_seconds: 0,
+            message: default_cooldown_message(),
+        }
+    }
+}
+
+fn default_cooldown_message() -> String {
+    String::from("还在冷却中，{seconds} 秒后再来")
+}
+
+fn default_hunger_per_hour() -> u32 {
+    5
+}
+
+fn default_energy_per_hour() -> u32 {
+    4
+}
+
+fn default_grumpy_minutes() -> u64 {
+    30
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Config {
+        let mut config: Config = serde_yaml::from_str(source).unwrap();
+        config.migrate();
+        config
+    }
+
+    #[test]
+    fn legacy_counter_files_become_counters() {
+        let config = parse("canteens: []\nmeow_counter_file: meow\ntwd2_counter_file: ./twd2\n");
+        let counters: Vec<_> = config
+            .counters
+            .iter()
+            .map(|c| {
+                let commands: Vec<_> = c.commands.iter().map(|c| c.command.as_str()).collect();
+                (c.key.as_str(), commands)
+            })
+            .collect();
+        assert_eq!(
+            counters,
+            [
+                ("meow", vec!["feedmeow", "feedmeowwd40"]),
+                ("twd2", vec!["feedtwd2"]),
+            ]
+        );
+        assert!(config.meow_counter_file.is_none());
+        assert!(config.twd2_counter_file.is_none());
+    }
+
+    #[test]
+    fn legacy_counter_files_outside_storage_are_kept() {
+        let config = parse(
+            "meow_counter_file: /var/lib/meow\n\
+             twd2_counter_file: twd2\n\
+             storage:\n  backend: sqlite\n  path: bot.db\n",
+        );
+        assert!(config.counters.is_empty());
+        assert!(config.meow_counter_file.is_some());
+        assert!(config.twd2_counter_file.is_some());
+    }
+}
@@ -0,0 +1,681 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::Marker;
+
+use crate::config::{Config, StorageConfig};
+
+/// A problem found in the application configuration.
+#[derive(Clone, Debug)]
+pub struct ValidationError {
+    /// Line number of the offending item in the config file, if known.
+    pub line: Option<usize>,
+
+    /// Path to the offending item, e.g. `canteen_sets.thu[2].weight`.
+    pub path: String,
+
+    /// Description of the problem.
+    pub message: String,
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}: {}", line, self.path, self.message),
+            None => write!(f, "{}: {}", self.path, self.message),
+        }
+    }
+}
+
+/// All problems found in the application configuration.
+#[derive(Clone, Debug)]
+pub struct ValidationErrors(pub Vec<ValidationError>);
+
+impl Display for ValidationErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, e) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", e)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ValidationErrors {}
+
+impl Config {
+    /// Validate the configuration.
+    ///
+    /// `source` is the YAML text the configuration was parsed from and is used to locate the line
+    /// numbers of the reported problems. This function reports all the problems found rather than
+    /// the first one.
+    pub fn validate(&self, source: &str) -> Result<(), ValidationErrors> {
+        let mut validator = Validator {
+            lines: LineIndex::build(source),
+            builtin_commands: crate::commands::builtin_command_names(self),
+            errors: Vec::new(),
+        };
+
+        if !(-12..=14).contains(&self.utc_offset_hours) {
+            validator.report(
+                "utc_offset_hours",
+                "the UTC offset must be between -12 and 14 hours",
+            );
+        }
+
+        let legacy_counters = [
+            ("meow_counter_file", &self.meow_counter_file),
+            ("twd2_counter_file", &self.twd2_counter_file),
+        ];
+        for (path, file) in legacy_counters {
+            if file.is_some() {
+                validator.report(
+                    path,
+                    "this counter of earlier versions can only be migrated if its file is named \
+                     after a valid counter key and lies in the directory of the file storage; \
+                     configure it under counters instead",
+                );
+            }
+        }
+
+        if self.canteen_sets.is_empty() {
+            validator.report("canteen_sets", "at least one canteen set is required");
+        }
+
+        for (set_name, canteens) in &self.canteen_sets {
+            let set_path = format!("canteen_sets.{}", set_name);
+            if set_name.is_empty() || set_name.chars().any(char::is_whitespace) {
+                validator.report(
+                    &set_path,
+                    "the name of a canteen set must be non-empty and contain no whitespace",
+                );
+            }
+            if canteens.is_empty() {
+                validator.report(&set_path, "the canteen set is empty");
+                continue;
+            }
+
+            let mut names = HashSet::new();
+            let mut weight_sum = Some(0u64);
+            for (i, canteen) in canteens.iter().enumerate() {
+                let canteen_path = format!("{}[{}]", set_path, i);
+                if canteen.name.trim().is_empty() {
+                    validator.report(
+                        &format!("{}.name", canteen_path),
+                        "the name of a canteen must not be empty",
+                    );
+                } else if !names.insert(canteen.name.as_str()) {
+                    validator.report(
+                        &format!("{}.name", canteen_path),
+                        &format!("duplicate canteen \"{}\"", canteen.name),
+                    );
+                }
+                weight_sum = weight_sum.and_then(|sum| sum.checked_add(canteen.weight));
+            }
+
+            match weight_sum {
+                Some(0) => validator.report(&set_path, "all the canteens have zero weight"),
+                None => validator.report(&set_path, "the sum of the weights overflows"),
+                _ => (),
+            }
+        }
+
+        if !self.canteen_sets.contains_key(&self.default_canteen_set) {
+            validator.report(
+                "default_canteen_set",
+                &format!("unknown canteen set \"{}\"", self.default_canteen_set),
+            );
+        }
+
+        if self.round_active_hours == 0 {
+            validator.report("round_active_hours", "must be positive");
+        }
+
+        if self.sanitizer.max_length == 0 {
+            validator.report("sanitizer.max_length", "must be positive");
+        }
+
+        match &self.storage {
+            StorageConfig::File { path } | StorageConfig::Sqlite { path }
+                if path.as_os_str().is_empty() =>
+            {
+                validator.report("storage.path", "the storage path must not be empty");
+            }
+            _ => (),
+        }
+
+        if self.adoption.cooldown.message.trim().is_empty() {
+            validator.report(
+                "adoption.cooldown.message",
+                "the cooldown message must not be empty",
+            );
+        }
+
+        let mut commands = HashSet::new();
+        let mut counter_keys = HashSet::new();
+        for (i, drink) in self.drinks.iter().enumerate() {
+            let drink_path = format!("drinks[{}]", i);
+            if drink.name.trim().is_empty() {
+                validator.report(
+                    &format!("{}.name", drink_path),
+                    "the name of a drink must not be empty",
+                );
+            }
+            if drink.description.trim().is_empty() {
+                validator.report(
+                    &format!("{}.description", drink_path),
+                    "the description of a drink must not be empty",
+                );
+            }
+
+            let names = std::iter::once((format!("{}.command", drink_path), &drink.command)).chain(
+                drink
+                    .aliases
+                    .iter()
+                    .enumerate()
+                    .map(|(j, alias)| (format!("{}.aliases[{}]", drink_path, j), alias)),
+            );
+            for (path, name) in names {
+                validator.check_command(&mut commands, &path, name);
+            }
+        }
+
+        for (i, counter) in self.counters.iter().enumerate() {
+            let counter_path = format!("counters[{}]", i);
+            if !is_valid_counter_key(&counter.key) {
+                validator.report(
+                    &format!("{}.key", counter_path),
+                    &format!(
+                        "invalid counter key \"{}\": a key must consist of 1 to 64 ASCII \
+                         letters, digits, underscores and hyphens",
+                        counter.key
+                    ),
+                );
+            } else if !counter_keys.insert(counter.key.as_str()) {
+                validator.report(
+                    &format!("{}.key", counter_path),
+                    &format!("duplicate counter key \"{}\"", counter.key),
+                );
+            }
+            if counter.commands.is_empty() {
+                validator.report(
+                    &format!("{}.commands", counter_path),
+                    "a counter must have at least one command",
+                );
+            }
+
+            if let Some(milestones) = &counter.milestones {
+                if milestones.template.trim().is_empty() {
+                    validator.report(
+                        &format!("{}.milestones.template", counter_path),
+                        "the template of the celebration message must not be empty",
+                    );
+                }
+                if milestones.every == 0
+                    && !milestones.powers_of_ten
+                    && milestones.palindrome_min_digits == 0
+                {
+                    validator.report(
+                        &format!("{}.milestones", counter_path),
+                        "at least one kind of milestones must be enabled",
+                    );
+                }
+            }
+
+            if counter.cooldown.message.trim().is_empty() {
+                validator.report(
+                    &format!("{}.cooldown.message", counter_path),
+                    "the cooldown message must not be empty",
+                );
+            }
+
+            let events_path = format!("{}.events", counter_path);
+            if !is_valid_probability(counter.events.critical_chance) {
+                validator.report(
+                    &format!("{}.critical_chance", events_path),
+                    "a probability must be between 0 and 1",
+                );
+            }
+            if counter.events.critical_message.trim().is_empty() {
+                validator.report(
+                    &format!("{}.critical_message", events_path),
+                    "the message of critical increments must not be empty",
+                );
+            }
+            for (j, drop) in counter.events.drops.iter().enumerate() {
+                let drop_path = format!("{}.drops[{}]", events_path, j);
+                if drop.item.trim().is_empty() {
+                    validator.report(
+                        &format!("{}.item", drop_path),
+                        "the name of an item must not be empty",
+                    );
+                }
+                if !is_valid_probability(drop.chance) {
+                    validator.report(
+                        &format!("{}.chance", drop_path),
+                        "a probability must be between 0 and 1",
+                    );
+                }
+                if drop.message.trim().is_empty() {
+                    validator.report(
+                        &format!("{}.message", drop_path),
+                        "the message of a drop must not be empty",
+                    );
+                }
+            }
+
+            if let Some(pet) = &counter.pet {
+                let pet_path = format!("{}.pet", counter_path);
+                validator.check_command(
+                    &mut commands,
+                    &format!("{}.command", pet_path),
+                    &pet.command,
+                );
+                if pet.description.trim().is_empty() {
+                    validator.report(
+                        &format!("{}.description", pet_path),
+                        "the description of a pet command must not be empty",
+                    );
+                }
+            }
+
+            if let Some(stats) = &counter.stats {
+                let stats_path = format!("{}.stats", counter_path);
+                validator.check_command(
+                    &mut commands,
+                    &format!("{}.command", stats_path),
+                    &stats.command,
+                );
+                if stats.description.trim().is_empty() {
+                    validator.report(
+                        &format!("{}.description", stats_path),
+                        "the description of a stats command must not be empty",
+                    );
+                }
+            }
+
+            for (j, cmd) in counter.commands.iter().enumerate() {
+                let cmd_path = format!("{}.commands[{}]", counter_path, j);
+                validator.check_command(
+                    &mut commands,
+                    &format!("{}.command", cmd_path),
+                    &cmd.command,
+                );
+                if cmd.description.trim().is_empty() {
+                    validator.report(
+                        &format!("{}.description", cmd_path),
+                        "the description of a counter command must not be empty",
+                    );
+                }
+                if cmd.template.trim().is_empty() {
+                    validator.report(
+                        &format!("{}.template", cmd_path),
+                        "the message template of a counter command must not be empty",
+                    );
+                }
+            }
+        }
+
+        let mut modifiers = HashSet::new();
+        let vocabularies = [
+            ("sizes", &self.drink_modifiers.sizes),
+            ("ices", &self.drink_modifiers.ices),
+            ("sugars", &self.drink_modifiers.sugars),
+        ];
+        for (kind, vocabulary) in vocabularies {
+            for (i, modifier) in vocabulary.iter().enumerate() {
+                let path = format!("drink_modifiers.{}[{}]", kind, i);
+                if modifier.is_empty() || modifier.chars().any(char::is_whitespace) {
+                    validator.report(
+                        &path,
+                        "a drink modifier must be non-empty and contain no whitespace",
+                    );
+                } else if !modifiers.insert(modifier.as_str()) {
+                    validator.report(&path, &format!("duplicate drink modifier \"{}\"", modifier));
+                }
+            }
+        }
+
+        for (keyword, emoji) in &self.drink_emojis.keywords {
+            let path = format!("drink_emojis.keywords.{}", keyword);
+            if keyword.trim().is_empty() {
+                validator.report(&path, "a drink emoji keyword must not be empty");
+            }
+            if emoji.trim().is_empty() {
+                validator.report(&path, "a drink emoji must not be empty");
+            }
+        }
+
+        if validator.errors.is_empty() {
+            Ok(())
+        } else {
+            validator.errors.sort_by_key(|e| e.line);
+            Err(ValidationErrors(validator.errors))
+        }
+    }
+}
+
+/// Determine whether the given string is a valid Telegram bot command, without the leading `/`.
+fn is_valid_command_name(name: &str) -> bool {
+    (1..=32).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+/// Determine whether the given value is a valid probability.
+fn is_valid_probability(p: f64) -> bool {
+    (0.0..=1.0).contains(&p)
+}
+
+/// Determine whether the given string is a valid counter key, which is also used as a file name.
+pub(super) fn is_valid_counter_key(key: &str) -> bool {
+    (1..=64).contains(&key.len())
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+struct Validator {
+    lines: LineIndex,
+
+    /// Names of the commands built into the bot, which commands in the configuration must not take.
+    builtin_commands: HashSet<String>,
+
+    errors: Vec<ValidationError>,
+}
+
+impl Validator {
+    fn report(&mut self, path: &str, message: &str) {
+        self.errors.push(ValidationError {
+            line: self.lines.line_of(path),
+            path: path.to_owned(),
+            message: message.to_owned(),
+        });
+    }
+
+    /// Check a command defined in the configuration.
+    ///
+    /// `commands` holds the commands checked so far and is used to detect duplicates.
+    fn check_command(&mut self, commands: &mut HashSet<String>, path: &str, name: &str) {
+        if !is_valid_command_name(name) {
+            self.report(
+                path,
+                &format!(
+                    "invalid command \"{}\": a command must consist of 1 to 32 lowercase \
+                     letters, digits and underscores",
+                    name
+                ),
+            );
+        } else if self.builtin_commands.contains(name) {
+            self.report(
+                path,
+                &format!("command \"{}\" conflicts with a builtin command", name),
+            );
+        } else if !commands.insert(name.to_owned()) {
+            self.report(path, &format!("duplicate command \"{}\"", name));
+        }
+    }
+}
+
+/// Map from the paths of the items in a YAML document to their line numbers.
+#[derive(Debug, Default)]
+struct LineIndex {
+    lines: HashMap<String, usize>,
+}
+
+impl LineIndex {
+    /// Build a line index of the given YAML document.
+    ///
+    /// Any syntax error stops the indexing silently; the items indexed so far are kept.
+    fn build(source: &str) -> Self {
+        let mut builder = LineIndexBuilder::default();
+        let mut parser = Parser::new(source.chars());
+        if let Err(e) = parser.load(&mut builder, false) {
+            log::debug!("Failed to index config file: {}", e);
+        }
+        builder.index
+    }
+
+    /// Get the line number of the item at the given path.
+    ///
+    /// If the item itself cannot be found, the line number of its closest ancestor is returned.
+    fn line_of(&self, path: &str) -> Option<usize> {
+        let mut path = path;
+        loop {
+            if let Some(line) = self.lines.get(path) {
+                return Some(*line);
+            }
+            path = &path[..path.rfind(['.', '['])?];
+        }
+    }
+}
+
+enum Frame {
+    Mapping {
+        path: String,
+        key: Option<(String, usize)>,
+    },
+    Sequence {
+        path: String,
+        next_index: usize,
+    },
+}
+
+#[derive(Default)]
+struct LineIndexBuilder {
+    index: LineIndex,
+    stack: Vec<Frame>,
+}
+
+impl LineIndexBuilder {
+    /// Determine the path and the line number of a new node starting at the given mark.
+    ///
+    /// Returns `None` if the node is a mapping key.
+    fn begin_node(&mut self, ev: &Event, mark: Marker) -> Option<String> {
+        let (path, line) = match self.stack.last_mut() {
+            None => (String::new(), mark.line()),
+            Some(Frame::Mapping { path, key }) => match key.take() {
+                Some((key, line)) => (join_key(path, &key), line),
+                None => {
+                    let name = match ev {
+                        Event::Scalar(value, ..) => value.clone(),
+                        _ => String::from("?"),
+                    };
+                    *key = Some((name, mark.line()));
+                    return None;
+                }
+            },
+            Some(Frame::Sequence { path, next_index }) => {
+                let path = format!("{}[{}]", path, next_index);
+                *next_index += 1;
+                (path, mark.line())
+            }
+        };
+        self.index.lines.entry(path.clone()).or_insert(line);
+        Some(path)
+    }
+}
+
+fn join_key(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_owned()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+impl MarkedEventReceiver for LineIndexBuilder {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        match ev {
+            Event::Scalar(..) | Event::Alias(..) => {
+                self.begin_node(&ev, mark);
+            }
+            Event::MappingStart(..) | Event::SequenceStart(..) => {
+                // Complex mapping keys are indexed under a placeholder path.
+                let path = self
+                    .begin_node(&ev, mark)
+                    .unwrap_or_else(|| String::from("?"));
+                let frame = match ev {
+                    Event::MappingStart(..) => Frame::Mapping { path, key: None },
+                    _ => Frame::Sequence {
+                        path,
+                        next_index: 0,
+                    },
+                };
+                self.stack.push(frame);
+            }
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CANTEENS: &str = "\
+canteen_sets:
+  thu:
+    - name: 桃李园
+      weight: 1
+    - name: 紫荆园
+      weight: 2
+default_canteen_set: thu
+";
+
+    /// Validate the given configuration and check that exactly the given problems are found.
+    ///
+    /// Each problem is given by its path, its line number and a part of its message.
+    fn assert_problems(source: &str, expected: &[(&str, usize, &str)]) {
+        let mut config: Config = serde_yaml::from_str(source).unwrap();
+        config.migrate();
+        let errors = match config.validate(source) {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors.0,
+        };
+        assert_eq!(errors.len(), expected.len(), "{:?}", errors);
+        for (e, (path, line, message)) in errors.iter().zip(expected) {
+            assert_eq!((e.path.as_str(), e.line), (*path, Some(*line)));
+            assert!(e.message.contains(message), "{:?}", e);
+        }
+    }
+
+    #[test]
+    fn valid_config_passes() {
+        assert_problems(CANTEENS, &[]);
+    }
+
+    #[test]
+    fn zero_weight_canteen_set_is_rejected() {
+        let source = CANTEENS
+            .replace("weight: 1", "weight: 0")
+            .replace("weight: 2", "weight: 0");
+        assert_problems(&source, &[("canteen_sets.thu", 2, "zero weight")]);
+    }
+
+    #[test]
+    fn duplicate_canteen_is_rejected() {
+        let source = CANTEENS.replace("紫荆园", "桃李园");
+        assert_problems(
+            &source,
+            &[("canteen_sets.thu[1].name", 5, "duplicate canteen")],
+        );
+    }
+
+    #[test]
+    fn unknown_default_canteen_set_is_rejected() {
+        let source = CANTEENS.replace("default_canteen_set: thu", "default_canteen_set: pku");
+        assert_problems(
+            &source,
+            &[("default_canteen_set", 7, "unknown canteen set")],
+        );
+    }
+
+    #[test]
+    fn invalid_and_builtin_commands_are_rejected() {
+        let source = format!(
+            "{}drinks:
+  - command: Milk-Tea
+    name: 奶茶
+    description: 线上喝奶茶
+  - command: help
+    name: 帮助
+    description: 线上喝帮助
+",
+            CANTEENS
+        );
+        assert_problems(
+            &source,
+            &[
+                ("drinks[0].command", 9, "invalid command"),
+                ("drinks[1].command", 12, "conflicts with a builtin command"),
+            ],
+        );
+    }
+
+    #[test]
+    fn probability_out_of_range_is_rejected() {
+        let source = format!(
+            "{}counters:
+  - key: meow
+    commands:
+      - command: feedmeow
+        description: 投喂猫咪
+        template: Meow
+    events:
+      critical_chance: 1.5
+      drops:
+        - item: 小鱼干
+          chance: -0.1
+          message: 小鱼干
+",
+            CANTEENS
+        );
+        assert_problems(
+            &source,
+            &[
+                ("counters[0].events.critical_chance", 15, "probability"),
+                ("counters[0].events.drops[0].chance", 18, "probability"),
+            ],
+        );
+    }
+
+    #[test]
+    fn line_index_locates_nested_items() {
+        let lines = LineIndex::build(
+            "\
+a:
+  b:
+    - c: 1
+      d:
+        e: 2
+    - [x, y]
+f: 3
+",
+        );
+        assert_eq!(lines.line_of("a"), Some(1));
+        assert_eq!(lines.line_of("a.b"), Some(2));
+        assert_eq!(lines.line_of("a.b[0]"), Some(3));
+        assert_eq!(lines.line_of("a.b[0].c"), Some(3));
+        assert_eq!(lines.line_of("a.b[0].d.e"), Some(5));
+        assert_eq!(lines.line_of("a.b[1][1]"), Some(6));
+        assert_eq!(lines.line_of("f"), Some(7));
+    }
+
+    #[test]
+    fn line_index_falls_back_to_closest_ancestor() {
+        let lines = LineIndex::build("a:\n  b:\n    - c: 1\n");
+        assert_eq!(lines.line_of("a.b[0].missing"), Some(3));
+        assert_eq!(lines.line_of("a.b[5].c"), Some(2));
+        assert_eq!(lines.line_of("a.missing.c"), Some(1));
+        assert_eq!(lines.line_of("missing"), None);
+    }
+}
@@ -1,7 +1,6 @@
 extern crate async_trait;
 extern crate clap;
-#[macro_use]
-extern crate lazy_static;
+extern crate futures;
 extern crate log;
 extern crate pretty_env_logger;
 extern crate rand;
@@ -9,12 +8,15 @@ extern crate serde;
 extern crate serde_yaml;
 extern crate teloxide;
 extern crate tokio;
+extern crate tokio_stream;
+extern crate yaml_rust;
 
 mod commands;
 mod config;
 mod services;
 mod utils;
 
+use std::error::Error;
 use std::path::{Path, PathBuf};
 
 use log::LevelFilter;
@@ -40,6 +42,12 @@ async fn run() {
                     .help("path to the config file")
                     .required(true),
             )
+            .arg(
+                clap::Arg::with_name("check-config")
+                    .long("check-config")
+                    .takes_value(false)
+                    .help("validate the config file and exit"),
+            )
             .arg(
                 clap::Arg::with_name("verbosity")
                     .short("v")
@@ -52,7 +60,18 @@ async fn run() {
     init_logger(args.occurrences_of("verbosity"));
 
     let config_path = PathBuf::from(args.value_of("config").unwrap());
-    let config = load_config(&config_path);
+    let config = match load_config(&config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("Invalid config file {}:\n{}", config_path.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    if args.is_present("check-config") {
+        println!("Config file {} is valid", config_path.display());
+        return;
+    }
 
     let token = get_env_var("TELEGRAM_TOKEN");
     let bot_name = get_env_var("TELEGRAM_BOT_NAME");
@@ -81,25 +100,23 @@ where
     }
 }
 
-fn load_config<P>(config_path: P) -> Config
+fn load_config<P>(config_path: P) -> Result<Config, Box<dyn Error>>
 where
     P: AsRef<Path>,
 {
     let file_content = match std::fs::read_to_string(config_path) {
         Ok(content) => content,
-        Err(e) => {
-            log::error!("Failed to read config file: {}", e);
-            std::process::exit(1);
-        }
+        Err(e) => return Err(format!("Failed to read config file: {}", e).into()),
     };
 
-    match serde_yaml::from_str(&file_content) {
+    let mut config: Config = match serde_yaml::from_str(&file_content) {
         Ok(config) => config,
-        Err(e) => {
-            log::error!("Failed to parse config file: {}", e);
-            std::process::exit(1);
-        }
-    }
+        Err(e) => return Err(format!("Failed to parse config file: {}", e).into()),
+    };
+
+    config.migrate();
+    config.validate(&file_content)?;
+    Ok(config)
 }
 
 fn init_logger(verbosity: u64) {
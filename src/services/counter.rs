@@ -1,53 +1,862 @@
-use std::path::PathBuf;
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Mutex;
+mod merge;
 
-/// Provide a basic counter service.
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::services::flusher::{Flush, Flusher};
+use crate::services::pet::PetState;
+use crate::services::storage::Storage;
+
+/// Values of a counter in the global, the per-chat and the per-user scopes.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct CounterState {
+    /// The value of the counter across all chats.
+    #[serde(default)]
+    pub global: u64,
+
+    /// The values of the counter in each chat, keyed by chat ID.
+    #[serde(default)]
+    pub chats: HashMap<i64, u64>,
+
+    /// The values of the counter increased by each user, keyed by user ID.
+    #[serde(default)]
+    pub users: HashMap<i64, u64>,
+
+    /// The tallies of the users in each chat, keyed by chat ID and then by user ID.
+    #[serde(default)]
+    pub tallies: HashMap<i64, HashMap<i64, Tally>>,
+
+    /// Display names of the users who increased the counter, keyed by user ID.
+    #[serde(default)]
+    pub names: HashMap<i64, String>,
+
+    /// The state of the pet simulated by the counter.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pet: Option<PetState>,
+
+    /// The profile of the entity counted by the counter, e.g. a pet adopted by a chat.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<Profile>,
+}
+
+/// The profile of a named entity counted by a counter.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Profile {
+    /// The name of the entity.
+    pub name: String,
+
+    /// The species of the entity.
+    pub species: String,
+
+    /// When the entity was created, in seconds since the Unix epoch.
+    pub created_at: u64,
+}
+
+/// Number of times a user increased a counter in a chat.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Tally {
+    /// Number of increments of all time.
+    #[serde(default)]
+    pub total: u64,
+
+    /// Index of the week of the latest increment, see `week_of`.
+    #[serde(default)]
+    pub week: u64,
+
+    /// Number of increments in the week of the latest increment.
+    #[serde(default)]
+    pub week_total: u64,
+}
+
+/// The period covered by a leaderboard.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Period {
+    /// The current week.
+    Week,
+
+    /// All time.
+    All,
+}
+
+/// An entry of a leaderboard.
+#[derive(Clone, Debug)]
+pub struct Feeder {
+    /// ID of the user.
+    pub user_id: i64,
+
+    /// Display name of the user.
+    pub name: String,
+
+    /// Number of times the user increased the counter.
+    pub count: u64,
+}
+
+/// Values of a counter after an increment.
+#[derive(Clone, Copy, Debug)]
+pub struct CounterValues {
+    /// The value of the counter across all chats.
+    pub global: u64,
+
+    /// The value of the counter in the chat where it was increased.
+    pub chat: u64,
+
+    /// The value of the counter increased by the user who increased it.
+    pub user: u64,
+}
+
+/// An increment of a counter.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CounterEvent {
+    /// When the counter was increased, in seconds since the Unix epoch.
+    pub time: u64,
+
+    /// ID of the chat where the counter was increased.
+    pub chat_id: i64,
+
+    /// ID of the user who increased the counter.
+    pub user_id: i64,
+}
+
+/// Provide a counter service that counts globally, per chat and per user.
+///
+/// Increments only update the counter in memory. A background task saves the counter into the
+/// storage shortly after it changes, coalescing rapid increments into a single write. Every
+/// increment is also recorded as an event, appended to the event log of the counter.
+///
+/// Another background task polls the storage for changes made to the counter by others, e.g. an
+/// operator restoring the counter from a backup, and reloads it. Such changes are also reloaded
+/// right before every write rather than overwritten. Changes made by the bot that are not saved
+/// yet are merged into the reloaded counter rather than lost.
 pub struct CounterService {
-    path: PathBuf,
-    counter: AtomicU64,
-    write_file_lock: Mutex<()>,
+    store: Arc<CounterStore>,
 }
 
 impl CounterService {
-    /// Create a new counter service with the given file as the backing file.
-    pub fn new<P>(file_path: P) -> Self
-    where
-        P: Into<PathBuf>,
-    {
-        let path = file_path.into();
-        let init_counter: u64 = {
-            match std::fs::read_to_string(&path) {
-                Ok(s) => match s.parse() {
-                    Ok(n) => n,
-                    Err(e) => {
-                        log::warn!("Failed to parse meow counter: {}", e);
-                        0
+    /// Create a new counter service storing the counter under the given key in the given storage.
+    ///
+    /// A document containing a single number, as written by earlier versions, is loaded as the
+    /// global value of the counter. The counter starts from zero if there is no such document. An
+    /// error is returned if the document or the event log cannot be loaded or the document cannot
+    /// be parsed, rather than resetting the counter.
+    ///
+    /// Weeks start at midnight on Monday in the time zone of the given UTC offset in seconds.
+    ///
+    /// This function must be called within a Tokio runtime.
+    pub fn new(
+        storage: Arc<dyn Storage>,
+        key: &str,
+        utc_offset: i64,
+    ) -> Result<Self, Box<dyn Error>> {
+        let content = storage.load(key)?;
+        let state = match &content {
+            Some(content) => {
+                parse_state(content).map_err(|e| format!("Counter {} is corrupt: {}", key, e))?
+            }
+            None => CounterState::default(),
+        };
+        let events_key = format!("{}.events", key);
+        let events = load_events(&*storage, &events_key)?;
+
+        let store = Arc::new(CounterStore {
+            storage,
+            key: key.to_owned(),
+            utc_offset,
+            synced: Mutex::new(Synced {
+                content,
+                state: state.clone(),
+            }),
+            state: Mutex::new(state),
+            events_key,
+            events: Mutex::new(EventLog {
+                events,
+                pending: Vec::new(),
+            }),
+            flusher: Flusher::new(format!("counter {}", key)),
+        });
+        crate::services::flusher::spawn(store.clone());
+        tokio::spawn(run_watcher(store.clone()));
+
+        Ok(Self { store })
+    }
+
+    /// Load the profile of the counter stored under the given key in the given storage, without
+    /// loading the counter itself.
+    pub fn load_profile(
+        storage: &dyn Storage,
+        key: &str,
+    ) -> Result<Option<Profile>, Box<dyn Error>> {
+        match storage.load(key)? {
+            Some(content) => Ok(parse_state(&content)
+                .map_err(|e| format!("Counter {} is corrupt: {}", key, e))?
+                .profile),
+            None => Ok(None),
+        }
+    }
+
+    /// Increase the counter in the given chat on behalf of the given user and get the updated
+    /// counter values.
+    ///
+    /// `user` is the ID and the display name of the user.
+    pub fn increase(&self, chat_id: i64, user: (i64, &str)) -> CounterValues {
+        let (user_id, user_name) = user;
+        let time = crate::utils::get_unix_timestamp();
+        let week = self.store.current_week();
+
+        let mut state = self.store.state.lock().unwrap();
+        state.global += 1;
+        let chat = {
+            let value = state.chats.entry(chat_id).or_default();
+            *value += 1;
+            *value
+        };
+        let user = {
+            let value = state.users.entry(user_id).or_default();
+            *value += 1;
+            *value
+        };
+        let tally = state
+            .tallies
+            .entry(chat_id)
+            .or_default()
+            .entry(user_id)
+            .or_default();
+        tally.total += 1;
+        if tally.week == week {
+            tally.week_total += 1;
+        } else {
+            tally.week = week;
+            tally.week_total = 1;
+        }
+        state.names.insert(user_id, user_name.to_owned());
+
+        let mut log = self.store.events.lock().unwrap();
+        let event = CounterEvent {
+            time,
+            chat_id,
+            user_id,
+        };
+        log.events.push(event);
+        log.pending.push(format_event(&event));
+        drop(log);
+        let values = CounterValues {
+            global: state.global,
+            chat,
+            user,
+        };
+        drop(state);
+
+        self.store.mark_dirty();
+
+        values
+    }
+
+    /// Set the value of the counter in the given chat, changing its global value by the same
+    /// amount.
+    ///
+    /// This function returns the previous global value, the previous value in the chat and the new
+    /// global value of the counter, or `None` without changing the counter if the new global value
+    /// would overflow.
+    pub fn set(&self, chat_id: i64, value: u64) -> Option<(u64, u64, u64)> {
+        let mut state = self.store.state.lock().unwrap();
+        let previous_chat = state.chats.get(&chat_id).copied().unwrap_or_default();
+        let previous_global = state.global;
+        let global = if value >= previous_chat {
+            previous_global.checked_add(value - previous_chat)?
+        } else {
+            previous_global.saturating_sub(previous_chat - value)
+        };
+        state.chats.insert(chat_id, value);
+        state.global = global;
+        drop(state);
+
+        self.store.mark_dirty();
+
+        Some((previous_global, previous_chat, global))
+    }
+
+    /// Revert the latest increment of the counter in the given chat and get the reverted increment
+    /// together with the updated counter values.
+    ///
+    /// The revert is recorded in the event log. This function returns `None` if there is no
+    /// recorded increment in the chat.
+    pub fn undo(&self, chat_id: i64) -> Option<(CounterEvent, CounterValues)> {
+        let mut state = self.store.state.lock().unwrap();
+        let mut log = self.store.events.lock().unwrap();
+        let index = log.events.iter().rposition(|e| e.chat_id == chat_id)?;
+        let event = log.events.remove(index);
+        log.pending.push(format!("-{}", format_event(&event)));
+        drop(log);
+
+        state.global = state.global.saturating_sub(1);
+        let chat = {
+            let value = state.chats.entry(chat_id).or_default();
+            *value = value.saturating_sub(1);
+            *value
+        };
+        let user = {
+            let value = state.users.entry(event.user_id).or_default();
+            *value = value.saturating_sub(1);
+            *value
+        };
+        if let Some(tally) = state
+            .tallies
+            .get_mut(&chat_id)
+            .and_then(|tallies| tallies.get_mut(&event.user_id))
+        {
+            tally.total = tally.total.saturating_sub(1);
+            if tally.week == week_of(event.time, self.store.utc_offset) {
+                tally.week_total = tally.week_total.saturating_sub(1);
+            }
+        }
+        let values = CounterValues {
+            global: state.global,
+            chat,
+            user,
+        };
+        drop(state);
+
+        self.store.mark_dirty();
+
+        Some((event, values))
+    }
+
+    /// Get the users who increased the counter the most in the given period, in descending order of
+    /// their counts.
+    ///
+    /// The leaderboard covers the given chat, or all chats if `chat_id` is `None`.
+    pub fn leaderboard(&self, chat_id: Option<i64>, period: Period) -> Vec<Feeder> {
+        let week = self.store.current_week();
+        let count_of = |tally: &Tally| match period {
+            Period::Week if tally.week == week => tally.week_total,
+            Period::Week => 0,
+            Period::All => tally.total,
+        };
+
+        let state = self.store.state.lock().unwrap();
+        let mut counts: HashMap<i64, u64> = HashMap::new();
+        match (chat_id, period) {
+            (Some(chat_id), _) => {
+                if let Some(tallies) = state.tallies.get(&chat_id) {
+                    for (user_id, tally) in tallies {
+                        *counts.entry(*user_id).or_default() += count_of(tally);
                     }
-                },
-                Err(e) => {
-                    log::warn!("Failed to read meow counter from file: {}", e);
-                    0
                 }
             }
+            // The per-user values also cover the increments made before tallies were recorded.
+            (None, Period::All) => counts = state.users.clone(),
+            (None, Period::Week) => {
+                for (user_id, tally) in state.tallies.values().flatten() {
+                    *counts.entry(*user_id).or_default() += count_of(tally);
+                }
+            }
+        }
+
+        let mut feeders: Vec<_> = counts
+            .into_iter()
+            .filter(|(_, count)| *count > 0)
+            .map(|(user_id, count)| Feeder {
+                user_id,
+                name: state
+                    .names
+                    .get(&user_id)
+                    .cloned()
+                    .unwrap_or_else(|| String::from("神秘人")),
+                count,
+            })
+            .collect();
+        feeders.sort_by_key(|feeder| (std::cmp::Reverse(feeder.count), feeder.user_id));
+        feeders
+    }
+
+    /// Get the value of the counter across all chats and its value in the given chat.
+    pub fn get(&self, chat_id: i64) -> (u64, u64) {
+        let state = self.store.state.lock().unwrap();
+        let chat = state.chats.get(&chat_id).copied().unwrap_or_default();
+        (state.global, chat)
+    }
+
+    /// Get the increments of the counter since the given time, in chronological order.
+    ///
+    /// The increments in the given chat are returned, or those in all chats if `chat_id` is
+    /// `None`.
+    pub fn events_since(&self, chat_id: Option<i64>, since: u64) -> Vec<CounterEvent> {
+        let log = self.store.events.lock().unwrap();
+        let start = log.events.partition_point(|e| e.time < since);
+        log.events[start..]
+            .iter()
+            .filter(|e| chat_id.is_none_or(|chat_id| e.chat_id == chat_id))
+            .copied()
+            .collect()
+    }
+
+    /// Get the profile of the entity counted by the counter.
+    pub fn profile(&self) -> Option<Profile> {
+        self.store.state.lock().unwrap().profile.clone()
+    }
+
+    /// Set the profile of the entity counted by the counter if it has no profile yet.
+    ///
+    /// This function returns the existing profile if there is one.
+    pub fn create_profile(&self, profile: Profile) -> Result<(), Profile> {
+        let mut state = self.store.state.lock().unwrap();
+        if let Some(existing) = &state.profile {
+            return Err(existing.clone());
+        }
+        state.profile = Some(profile);
+        drop(state);
+
+        self.store.mark_dirty();
+        Ok(())
+    }
+
+    /// Get the state of the pet simulated by the counter, as of its last update.
+    pub fn pet(&self) -> PetState {
+        let state = self.store.state.lock().unwrap();
+        match &state.pet {
+            Some(pet) => pet.clone(),
+            None => PetState::new(crate::utils::get_unix_timestamp()),
+        }
+    }
+
+    /// Update the state of the pet simulated by the counter.
+    pub fn update_pet<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut PetState) -> R,
+    {
+        let mut state = self.store.state.lock().unwrap();
+        let pet = state
+            .pet
+            .get_or_insert_with(|| PetState::new(crate::utils::get_unix_timestamp()));
+        let ret = f(pet);
+        drop(state);
+
+        self.store.mark_dirty();
+
+        ret
+    }
+
+    /// Save the pending changes of the counter and its event log into the storage.
+    ///
+    /// This function blocks until the changes are saved.
+    pub fn flush(&self) {
+        self.store.flush();
+    }
+}
+
+/// The state of a counter shared with its background flusher.
+struct CounterStore {
+    storage: Arc<dyn Storage>,
+
+    key: String,
+    state: Mutex<CounterState>,
+
+    /// Offset of the time zone where weeks start from UTC, in seconds.
+    utc_offset: i64,
+
+    /// The counter as last loaded from or written into the storage.
+    synced: Mutex<Synced>,
+
+    events_key: String,
+    events: Mutex<EventLog>,
+
+    /// Tracks the unsaved changes of the state and the event log. Its write lock also serializes
+    /// reloads from the storage.
+    flusher: Flusher,
+}
+
+impl CounterStore {
+    /// Get the index of the current week, see `week_of`.
+    fn current_week(&self) -> u64 {
+        week_of(crate::utils::get_unix_timestamp(), self.utc_offset)
+    }
+
+    /// Mark the state as changed and wake up the background flusher.
+    fn mark_dirty(&self) {
+        self.flusher.mark_dirty();
+    }
+
+    fn write_state(&self) -> Result<(), Box<dyn Error>> {
+        let state = self.state.lock().unwrap().clone();
+        let content = serde_yaml::to_string(&state)?;
+        self.storage.save(&self.key, &content)?;
+        *self.synced.lock().unwrap() = Synced {
+            content: Some(content),
+            state,
+        };
+        Ok(())
+    }
+
+    /// Reload the counter if it has been changed in the storage by others since it was last
+    /// loaded or written.
+    fn reload(&self) {
+        let _lock = self.flusher.lock();
+        match self.sync() {
+            Ok(true) => self.mark_dirty(),
+            Ok(false) => (),
+            Err(e) => log::warn!("Failed to reload counter {}: {}", self.key, e),
+        }
+    }
+
+    /// Adopt the changes made to the counter in the storage by others since it was last loaded or
+    /// written, which must be done with the write lock held.
+    ///
+    /// Changes made in memory since then are merged into the reloaded counter and logged as a
+    /// conflict, in which case this function returns `true` since the merged counter needs to be
+    /// written. A counter removed from the storage or changed into something that cannot be
+    /// parsed is ignored, and is overwritten by the next write.
+    fn sync(&self) -> Result<bool, Box<dyn Error>> {
+        let content = match self.storage.load(&self.key)? {
+            Some(content) => content,
+            None => return Ok(false),
+        };
+        let mut synced = self.synced.lock().unwrap();
+        if synced.content.as_deref() == Some(content.as_str()) {
+            return Ok(false);
+        }
+
+        let external = match parse_state(&content) {
+            Ok(external) => external,
+            Err(e) => {
+                log::warn!(
+                    "Ignoring corrupt external change to counter {}: {}",
+                    self.key,
+                    e
+                );
+                synced.content = Some(content);
+                return Ok(false);
+            }
+        };
+        let mut state = self.state.lock().unwrap();
+        let merged = if *state == synced.state {
+            log::info!(
+                "Reloaded counter {} changed externally: {} -> {}",
+                self.key,
+                state.global,
+                external.global
+            );
+            *state = external.clone();
+            false
+        } else {
+            let merged = merge::merge(&synced.state, &state, external.clone());
+            log::warn!(
+                "Counter {} was changed externally while it had unsaved changes, merged them: \
+                 {} before the external change, {} in memory, {} externally, {} merged",
+                self.key,
+                synced.state.global,
+                state.global,
+                external.global,
+                merged.global
+            );
+            *state = merged;
+            true
+        };
+        *synced = Synced {
+            content: Some(content),
+            state: external,
         };
+        Ok(merged)
+    }
 
-        Self {
-            path,
-            counter: AtomicU64::new(init_counter),
-            write_file_lock: Mutex::new(()),
+    /// Append the records that are not written yet to the event log.
+    fn append_events(&self) -> Result<(), Box<dyn Error>> {
+        let records = self.events.lock().unwrap().pending.clone();
+        if records.is_empty() {
+            return Ok(());
         }
+
+        self.storage.append_log(&self.events_key, &records)?;
+        self.events.lock().unwrap().pending.drain(..records.len());
+        Ok(())
+    }
+}
+
+impl Flush for CounterStore {
+    fn flusher(&self) -> &Flusher {
+        &self.flusher
+    }
+
+    fn flush(&self) {
+        self.flusher.flush(|| {
+            // Writing over a change made by others, e.g. a counter restored from a backup, would
+            // lose it before the watcher sees it.
+            self.sync()
+                .map_err(|e| format!("cannot check for external changes: {}", e))?;
+            let state = self.write_state();
+            let events = self
+                .append_events()
+                .map_err(|e| format!("cannot append events to {}: {}", self.events_key, e).into());
+            match (state, events) {
+                (Err(e1), Err(e2)) => Err(format!("{}; {}", e1, e2).into()),
+                (state, events) => state.and(events),
+            }
+        });
+    }
+}
+
+/// A counter as last loaded from or written into the storage.
+struct Synced {
+    /// The document holding the counter, if any.
+    content: Option<String>,
+
+    state: CounterState,
+}
+
+/// The increments of a counter.
+struct EventLog {
+    /// The increments in chronological order.
+    events: Vec<CounterEvent>,
+
+    /// The records that are not written into the event log yet.
+    pending: Vec<String>,
+}
+
+/// Parse a document holding a counter.
+///
+/// A document containing a single number, as written by earlier versions, is parsed as the global
+/// value of the counter.
+fn parse_state(content: &str) -> Result<CounterState, serde_yaml::Error> {
+    match content.trim().parse() {
+        Ok(global) => Ok(CounterState {
+            global,
+            ..CounterState::default()
+        }),
+        Err(_) => serde_yaml::from_str(content),
     }
+}
 
-    /// Increase the counter and get the updated counter value.
-    pub fn increase(&self) -> u64 {
-        let ret = self.counter.fetch_add(1, Ordering::Relaxed) + 1;
+/// Load the increments of a counter from the given event log.
+///
+/// Each record of the event log holds the time, the chat ID and the user ID of an increment. A
+/// record prefixed by `-` reverts the latest matching increment. Records that cannot be parsed,
+/// e.g. a record cut off by a crash, are skipped.
+fn load_events(storage: &dyn Storage, key: &str) -> Result<Vec<CounterEvent>, Box<dyn Error>> {
+    let records = storage
+        .load_log(key)
+        .map_err(|e| format!("Cannot load counter events {}: {}", key, e))?;
 
-        let _lock = self.write_file_lock.lock().unwrap();
-        if let Err(e) = std::fs::write(&self.path, ret.to_string()) {
-            log::warn!("Failed to write counter into file: {}", e);
+    let mut events = Vec::new();
+    for (i, record) in records.iter().enumerate() {
+        let (revert, record) = match record.strip_prefix('-') {
+            Some(record) => (true, record),
+            None => (false, record.as_str()),
+        };
+        match parse_event(record) {
+            Some(event) if revert => {
+                if let Some(index) = events.iter().rposition(|e| *e == event) {
+                    events.remove(index);
+                }
+            }
+            Some(event) => events.push(event),
+            None => log::warn!(
+                "Skipping malformed record {} of counter events {}",
+                i + 1,
+                key
+            ),
         }
+    }
+    events.sort_by_key(|e| e.time);
+    Ok(events)
+}
 
-        ret
+/// Format an increment as a record of an event log.
+fn format_event(event: &CounterEvent) -> String {
+    format!("{} {} {}", event.time, event.chat_id, event.user_id)
+}
+
+/// Parse a record of an event log.
+fn parse_event(record: &str) -> Option<CounterEvent> {
+    let mut fields = record.split_whitespace();
+    let event = CounterEvent {
+        time: fields.next()?.parse().ok()?,
+        chat_id: fields.next()?.parse().ok()?,
+        user_id: fields.next()?.parse().ok()?,
+    };
+    match fields.next() {
+        Some(_) => None,
+        None => Some(event),
+    }
+}
+
+/// Get the index of the week since the Unix epoch containing the given time, where weeks start at
+/// midnight on Monday in the time zone of the given UTC offset in seconds.
+fn week_of(time: u64, utc_offset: i64) -> u64 {
+    // The Unix epoch is a Thursday.
+    (crate::utils::local_day(time, utc_offset) + 3) / 7
+}
+
+/// How often the background watcher checks the storage for external changes to a counter.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Reload the given counter whenever it is changed in the storage by others.
+async fn run_watcher(store: Arc<CounterStore>) {
+    loop {
+        tokio::time::sleep(RELOAD_INTERVAL).await;
+
+        let store = store.clone();
+        if let Err(e) = tokio::task::spawn_blocking(move || store.reload()).await {
+            log::warn!("Counter watcher failed: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::storage::MemoryStorage;
+
+    const KEY: &str = "meow";
+
+    fn open(storage: &Arc<MemoryStorage>) -> CounterService {
+        CounterService::new(storage.clone(), KEY, 0).unwrap()
+    }
+
+    /// Write the given counter into the storage as if it was restored from a backup.
+    fn restore(storage: &MemoryStorage, state: &CounterState) {
+        storage
+            .save(KEY, &serde_yaml::to_string(state).unwrap())
+            .unwrap();
+    }
+
+    fn load(storage: &MemoryStorage) -> CounterState {
+        parse_state(&storage.load(KEY).unwrap().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn external_restore_is_reloaded() {
+        let storage = Arc::new(MemoryStorage::new());
+        let counter = open(&storage);
+        counter.increase(1, (10, "a"));
+        counter.flush();
+
+        restore(
+            &storage,
+            &CounterState {
+                global: 50,
+                chats: HashMap::from([(1, 40)]),
+                ..CounterState::default()
+            },
+        );
+        counter.store.reload();
+        assert_eq!(counter.get(1), (50, 40));
+    }
+
+    #[tokio::test]
+    async fn external_restore_keeps_unsaved_increments() {
+        let storage = Arc::new(MemoryStorage::new());
+        let counter = open(&storage);
+        for _ in 0..3 {
+            counter.increase(1, (10, "a"));
+        }
+        counter.flush();
+
+        // Increments made after the last write are not saved yet.
+        counter.increase(1, (10, "a"));
+        counter.increase(1, (10, "a"));
+        counter.increase(2, (11, "b"));
+        restore(
+            &storage,
+            &CounterState {
+                global: 50,
+                chats: HashMap::from([(1, 40)]),
+                users: HashMap::from([(10, 40)]),
+                ..CounterState::default()
+            },
+        );
+        counter.store.reload();
+
+        assert_eq!(counter.get(1), (53, 42));
+        assert_eq!(counter.get(2), (53, 1));
+        let feeders = counter.leaderboard(None, Period::All);
+        let counts: Vec<_> = feeders.iter().map(|f| (f.user_id, f.count)).collect();
+        assert_eq!(counts, [(10, 42), (11, 1)]);
+
+        // The merged counter is written back.
+        counter.flush();
+        let saved = load(&storage);
+        assert_eq!(saved.global, 53);
+        assert_eq!(saved.chats, HashMap::from([(1, 42), (2, 1)]));
+    }
+
+    #[tokio::test]
+    async fn flush_keeps_external_restore() {
+        let storage = Arc::new(MemoryStorage::new());
+        let counter = open(&storage);
+        counter.increase(1, (10, "a"));
+        counter.flush();
+
+        // The counter is restored and increased before the watcher sees the restore.
+        restore(
+            &storage,
+            &CounterState {
+                global: 50,
+                chats: HashMap::from([(1, 40)]),
+                users: HashMap::from([(10, 40)]),
+                ..CounterState::default()
+            },
+        );
+        counter.increase(1, (10, "a"));
+        counter.flush();
+
+        assert_eq!(counter.get(1), (51, 41));
+        let saved = load(&storage);
+        assert_eq!(saved.global, 51);
+        assert_eq!(saved.chats, HashMap::from([(1, 41)]));
+        assert_eq!(saved.users, HashMap::from([(10, 41)]));
+    }
+
+    #[tokio::test]
+    async fn external_restore_from_last_week_rolls_tallies_over() {
+        let storage = Arc::new(MemoryStorage::new());
+        let counter = open(&storage);
+        counter.increase(1, (10, "a"));
+        counter.flush();
+        counter.increase(1, (10, "a"));
+        counter.increase(1, (10, "a"));
+
+        // The backup was taken last week, when the user had a tally of that week.
+        let week = counter.store.current_week();
+        let tally = Tally {
+            total: 20,
+            week: week - 1,
+            week_total: 8,
+        };
+        restore(
+            &storage,
+            &CounterState {
+                global: 20,
+                chats: HashMap::from([(1, 20)]),
+                users: HashMap::from([(10, 20)]),
+                tallies: HashMap::from([(1, HashMap::from([(10, tally)]))]),
+                ..CounterState::default()
+            },
+        );
+        counter.store.reload();
+
+        let all = counter.leaderboard(Some(1), Period::All);
+        assert_eq!(all[0].count, 22);
+        // Only the unsaved increments count for this week.
+        let this_week = counter.leaderboard(Some(1), Period::Week);
+        assert_eq!(this_week[0].count, 2);
+    }
+
+    #[tokio::test]
+    async fn corrupt_external_change_is_ignored() {
+        let storage = Arc::new(MemoryStorage::new());
+        let counter = open(&storage);
+        counter.increase(1, (10, "a"));
+        counter.flush();
+
+        storage.save(KEY, "global: [oops").unwrap();
+        counter.store.reload();
+        assert_eq!(counter.get(1), (1, 1));
+
+        // The next write replaces the corrupt document.
+        counter.increase(1, (10, "a"));
+        counter.flush();
+        assert_eq!(load(&storage).global, 2);
     }
 }
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+
+use crate::services::counter::CounterState;
+
+/// Merge the changes made to a counter in memory into the counter changed externally.
+///
+/// `base` is the counter both sides started from and `local` is the counter in memory. The
+/// increments made in memory since `base` are added to the values of `external`, and the pet and
+/// the profile changed in memory replace those of `external`.
+pub(super) fn merge(
+    base: &CounterState,
+    local: &CounterState,
+    external: CounterState,
+) -> CounterState {
+    let mut merged = external;
+    merged.global = apply_delta(merged.global, base.global, local.global);
+    merge_values(&mut merged.chats, &base.chats, &local.chats);
+    merge_values(&mut merged.users, &base.users, &local.users);
+
+    for (chat_id, tallies) in &local.tallies {
+        for (user_id, tally) in tallies {
+            let base_tally = base
+                .tallies
+                .get(chat_id)
+                .and_then(|tallies| tallies.get(user_id))
+                .copied()
+                .unwrap_or_default();
+            if *tally == base_tally {
+                continue;
+            }
+
+            let merged_tally = merged
+                .tallies
+                .entry(*chat_id)
+                .or_default()
+                .entry(*user_id)
+                .or_default();
+            merged_tally.total = apply_delta(merged_tally.total, base_tally.total, tally.total);
+
+            let base_week_total = if base_tally.week == tally.week {
+                base_tally.week_total
+            } else {
+                0
+            };
+            if merged_tally.week == tally.week {
+                merged_tally.week_total =
+                    apply_delta(merged_tally.week_total, base_week_total, tally.week_total);
+            } else if merged_tally.week < tally.week {
+                merged_tally.week = tally.week;
+                merged_tally.week_total = apply_delta(0, base_week_total, tally.week_total);
+            }
+        }
+    }
+
+    for (user_id, name) in &local.names {
+        if base.names.get(user_id) != Some(name) {
+            merged.names.insert(*user_id, name.clone());
+        }
+    }
+    if local.pet != base.pet {
+        merged.pet = local.pet.clone();
+    }
+    if local.profile != base.profile {
+        merged.profile = local.profile.clone();
+    }
+    merged
+}
+
+/// Add the change from `base` to `local` to the given value.
+fn apply_delta(value: u64, base: u64, local: u64) -> u64 {
+    (value as i128 + local as i128 - base as i128).clamp(0, u64::MAX as i128) as u64
+}
+
+/// Add the changes from `base` to `local` to the values with the same keys in `merged`.
+fn merge_values(
+    merged: &mut HashMap<i64, u64>,
+    base: &HashMap<i64, u64>,
+    local: &HashMap<i64, u64>,
+) {
+    for (key, value) in local {
+        let base = base.get(key).copied().unwrap_or_default();
+        if *value != base {
+            let merged = merged.entry(*key).or_default();
+            *merged = apply_delta(*merged, base, *value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::counter::{Profile, Tally};
+    use crate::services::pet::PetState;
+
+    fn tally(total: u64, week: u64, week_total: u64) -> Tally {
+        Tally {
+            total,
+            week,
+            week_total,
+        }
+    }
+
+    fn with_tally(total: u64, week: u64, week_total: u64) -> CounterState {
+        let tally = tally(total, week, week_total);
+        CounterState {
+            tallies: HashMap::from([(1, HashMap::from([(10, tally)]))]),
+            ..CounterState::default()
+        }
+    }
+
+    fn tally_of(state: &CounterState) -> Tally {
+        state.tallies[&1][&10]
+    }
+
+    #[test]
+    fn adds_increments_of_the_same_week() {
+        let merged = merge(
+            &with_tally(5, 10, 5),
+            &with_tally(7, 10, 7),
+            with_tally(20, 10, 3),
+        );
+        assert_eq!(tally_of(&merged), tally(22, 10, 5));
+    }
+
+    #[test]
+    fn keeps_tallies_of_a_newer_external_week() {
+        let merged = merge(
+            &with_tally(5, 10, 5),
+            &with_tally(7, 10, 7),
+            with_tally(20, 11, 3),
+        );
+        assert_eq!(tally_of(&merged), tally(22, 11, 3));
+    }
+
+    #[test]
+    fn takes_pet_and_profile_only_if_changed_locally() {
+        let base = CounterState::default();
+        let profile = Profile {
+            name: String::from("小橘"),
+            species: String::from("橘猫"),
+            created_at: 0,
+        };
+        let external = CounterState {
+            profile: Some(profile.clone()),
+            ..CounterState::default()
+        };
+        let local = CounterState {
+            pet: Some(PetState::new(100)),
+            ..CounterState::default()
+        };
+
+        let merged = merge(&base, &local, external);
+        assert_eq!(merged.profile, Some(profile));
+        assert_eq!(merged.pet, Some(PetState::new(100)));
+    }
+
+    #[test]
+    fn never_goes_below_zero() {
+        let base = CounterState {
+            global: 10,
+            ..CounterState::default()
+        };
+        let local = CounterState {
+            global: 4,
+            ..CounterState::default()
+        };
+        assert_eq!(merge(&base, &local, CounterState::default()).global, 0);
+    }
+}
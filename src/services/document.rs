@@ -0,0 +1,94 @@
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::services::flusher::{Flush, Flusher};
+use crate::services::storage::Storage;
+
+/// A value stored as a YAML document in a storage.
+///
+/// Updates only change the value in memory, and a background flusher saves it into the storage
+/// shortly after it changes.
+pub struct Document<T> {
+    inner: Arc<DocumentInner<T>>,
+}
+
+impl<T> Document<T>
+where
+    T: Serialize + DeserializeOwned + Default + Send + 'static,
+{
+    /// Load the value stored under the given key in the given storage.
+    ///
+    /// The default value is used if there is no such document. An error is returned if the
+    /// document cannot be loaded or parsed, rather than overwriting it later.
+    ///
+    /// This function must be called within a Tokio runtime.
+    pub fn load(storage: Arc<dyn Storage>, key: &str) -> Result<Self, Box<dyn Error>> {
+        let value = match storage.load(key)? {
+            Some(content) => serde_yaml::from_str(&content)
+                .map_err(|e| format!("Document {} is corrupt: {}", key, e))?,
+            None => T::default(),
+        };
+
+        let inner = Arc::new(DocumentInner {
+            storage,
+            key: key.to_owned(),
+            value: Mutex::new(value),
+            flusher: Flusher::new(format!("document {}", key)),
+        });
+        crate::services::flusher::spawn(inner.clone());
+
+        Ok(Self { inner })
+    }
+
+    /// Read the value.
+    pub fn read<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        f(&self.inner.value.lock().unwrap())
+    }
+
+    /// Update the value and schedule it to be saved.
+    pub fn update<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        let ret = f(&mut self.inner.value.lock().unwrap());
+        self.inner.flusher.mark_dirty();
+        ret
+    }
+
+    /// Save the pending changes of the value into the storage.
+    ///
+    /// This function blocks until the changes are saved.
+    pub fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// A document shared with its background flusher.
+struct DocumentInner<T> {
+    storage: Arc<dyn Storage>,
+    key: String,
+    value: Mutex<T>,
+    flusher: Flusher,
+}
+
+impl<T> Flush for DocumentInner<T>
+where
+    T: Serialize + Send + 'static,
+{
+    fn flusher(&self) -> &Flusher {
+        &self.flusher
+    }
+
+    fn flush(&self) {
+        self.flusher.flush(|| {
+            let content = serde_yaml::to_string(&*self.value.lock().unwrap())?;
+            self.storage.save(&self.key, &content)
+        });
+    }
+}
@@ -0,0 +1,106 @@
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use tokio::sync::Notify;
+
+/// How long a background flusher waits after a change before saving, so that rapid changes are
+/// saved at once.
+const FLUSH_DELAY: Duration = Duration::from_secs(1);
+
+/// Track the unsaved changes of a value saved into a storage by a background task.
+///
+/// Changes only mark the value as dirty. The task spawned by `spawn` saves the value shortly after
+/// it changes, coalescing rapid changes into a single write. A failed save is retried on the next
+/// flush.
+pub struct Flusher {
+    /// Description of the value in the log messages, e.g. `counter meow`.
+    what: String,
+
+    /// Whether the value has changed since it was last saved.
+    dirty: AtomicBool,
+
+    /// Serialize saves of the value.
+    write_lock: Mutex<()>,
+
+    /// Notified when the value changes.
+    changed: Notify,
+}
+
+impl Flusher {
+    /// Create a new flusher of a clean value described by `what` in the log messages.
+    pub fn new<S>(what: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Self {
+            what: what.into(),
+            dirty: AtomicBool::new(false),
+            write_lock: Mutex::new(()),
+            changed: Notify::new(),
+        }
+    }
+
+    /// Mark the value as changed and wake up the background task.
+    pub fn mark_dirty(&self) {
+        self.dirty.store(true, Ordering::Release);
+        self.changed.notify_one();
+    }
+
+    /// Prevent the value from being saved until the returned guard is dropped.
+    pub fn lock(&self) -> MutexGuard<'_, ()> {
+        self.write_lock.lock().unwrap()
+    }
+
+    /// Save the value with the given function if it has changed since it was last saved.
+    ///
+    /// This function blocks until the value is saved. The function is called with the write lock
+    /// held, see `lock`.
+    pub fn flush<F>(&self, save: F)
+    where
+        F: FnOnce() -> Result<(), Box<dyn Error>>,
+    {
+        let _lock = self.lock();
+        if !self.dirty.swap(false, Ordering::AcqRel) {
+            return;
+        }
+
+        if let Err(e) = save() {
+            log::warn!("Failed to save {}: {}", self.what, e);
+            // Retry on the next flush.
+            self.dirty.store(true, Ordering::Release);
+        }
+    }
+}
+
+/// A value whose changes are saved by a background task, see `Flusher`.
+pub trait Flush: Send + Sync + 'static {
+    /// Get the flusher tracking the changes of the value.
+    fn flusher(&self) -> &Flusher;
+
+    /// Save the pending changes of the value.
+    ///
+    /// This function blocks until the changes are saved.
+    fn flush(&self);
+}
+
+/// Spawn the background task saving the given value whenever it changes.
+///
+/// This function must be called within a Tokio runtime.
+pub fn spawn<T>(value: Arc<T>)
+where
+    T: Flush,
+{
+    tokio::spawn(async move {
+        loop {
+            value.flusher().changed.notified().await;
+            tokio::time::sleep(FLUSH_DELAY).await;
+
+            let task_value = value.clone();
+            if let Err(e) = tokio::task::spawn_blocking(move || task_value.flush()).await {
+                log::warn!("Flusher of {} failed: {}", value.flusher().what, e);
+            }
+        }
+    });
+}
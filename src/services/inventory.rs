@@ -0,0 +1,55 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::sync::Arc;
+
+use crate::services::document::Document;
+use crate::services::storage::Storage;
+
+/// Key of the document holding the inventories, which cannot collide with counter keys.
+const INVENTORIES_KEY: &str = "loot.inventories";
+
+/// Provide per-user inventories of collected items backed by a storage.
+///
+/// Like counters, additions only update the inventories in memory, and a background task saves
+/// them into the storage shortly after they change.
+pub struct InventoryService {
+    /// Number of each item collected by each user, keyed by user ID and then by item name.
+    inventories: Document<HashMap<i64, BTreeMap<String, u64>>>,
+}
+
+impl InventoryService {
+    /// Create a new inventory service backed by the given storage.
+    ///
+    /// An error is returned if the inventories cannot be loaded or parsed.
+    ///
+    /// This function must be called within a Tokio runtime.
+    pub fn new(storage: Arc<dyn Storage>) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            inventories: Document::load(storage, INVENTORIES_KEY)?,
+        })
+    }
+
+    /// Add an item to the inventory of the given user.
+    pub fn add(&self, user_id: i64, item: &str) {
+        self.inventories.update(|inventories| {
+            *inventories
+                .entry(user_id)
+                .or_default()
+                .entry(item.to_owned())
+                .or_default() += 1;
+        });
+    }
+
+    /// Get the inventory of the given user.
+    pub fn get(&self, user_id: i64) -> BTreeMap<String, u64> {
+        self.inventories
+            .read(|inventories| inventories.get(&user_id).cloned().unwrap_or_default())
+    }
+
+    /// Save the pending changes of the inventories into the storage.
+    ///
+    /// This function blocks until the changes are saved.
+    pub fn flush(&self) {
+        self.inventories.flush();
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::services::document::Document;
+use crate::services::storage::Storage;
+
+/// The drink ledger of a single chat.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ChatLedger {
+    /// Display names of the users appearing in the ledger, keyed by user ID.
+    #[serde(default)]
+    pub names: HashMap<i64, String>,
+
+    /// The drinks poured by each user for each other user in the chat, keyed by the ID of the user
+    /// who poured the drinks and then by the ID of the recipient.
+    #[serde(default)]
+    pub drinks: HashMap<i64, HashMap<i64, PairTally>>,
+
+    /// Number of poured drinks each user accepted and declined, keyed by user ID.
+    #[serde(default)]
+    pub responses: HashMap<i64, DrinkResponses>,
+}
+
+/// Number of drinks a user poured for another user.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub struct PairTally {
+    /// Number of drinks poured.
+    #[serde(default)]
+    pub poured: u64,
+
+    /// Number of the poured drinks the recipient declined.
+    #[serde(default)]
+    pub declined: u64,
+}
+
+/// Number of poured drinks a user accepted and declined.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub struct DrinkResponses {
+    /// Number of drinks the user accepted.
+    pub accepted: u64,
+
+    /// Number of drinks the user declined.
+    pub declined: u64,
+}
+
+/// Number of drinks given and received by a user.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DrinkStats {
+    /// Number of drinks the user poured for others.
+    pub given: u64,
+
+    /// Number of drinks the user received from others.
+    pub received: u64,
+
+    /// Number of received drinks the user accepted and declined.
+    pub responses: DrinkResponses,
+}
+
+/// Net number of drinks a user owes another user.
+#[derive(Clone, Copy, Debug)]
+pub struct Debt {
+    /// ID of the user who owes the drinks.
+    pub debtor: i64,
+
+    /// ID of the user who is owed the drinks.
+    pub creditor: i64,
+
+    /// Net number of drinks owed.
+    pub drinks: u64,
+}
+
+impl ChatLedger {
+    /// Get the display name of the given user.
+    pub fn name_of(&self, user_id: i64) -> &str {
+        self.names
+            .get(&user_id)
+            .map(String::as_str)
+            .unwrap_or("神秘人")
+    }
+
+    /// Iterate over the users who poured drinks for others, the recipients and the drinks poured.
+    fn pairs(&self) -> impl Iterator<Item = (i64, i64, PairTally)> + '_ {
+        self.drinks.iter().flat_map(|(from, recipients)| {
+            recipients
+                .iter()
+                .map(move |(to, tally)| (*from, *to, *tally))
+        })
+    }
+
+    /// Get the number of drinks given and received by each user in the chat.
+    pub fn stats(&self) -> HashMap<i64, DrinkStats> {
+        let mut stats: HashMap<i64, DrinkStats> = HashMap::new();
+        for (from, to, tally) in self.pairs() {
+            stats.entry(from).or_default().given += tally.poured;
+            stats.entry(to).or_default().received += tally.poured;
+        }
+        for (user_id, responses) in &self.responses {
+            stats.entry(*user_id).or_default().responses = *responses;
+        }
+        stats
+    }
+
+    /// Get the net debts between each pair of users in the chat.
+    ///
+    /// A user who received more drinks from another user than they gave back owes the difference.
+    /// Declined drinks are not owed.
+    pub fn debts(&self) -> Vec<Debt> {
+        // Drinks poured from the first user to the second user of each pair, where the first user
+        // has the smaller ID.
+        let mut pairs: BTreeMap<(i64, i64), i64> = BTreeMap::new();
+        for (from, to, tally) in self.pairs() {
+            let drinks = tally.poured.saturating_sub(tally.declined) as i64;
+            if from < to {
+                *pairs.entry((from, to)).or_default() += drinks;
+            } else {
+                *pairs.entry((to, from)).or_default() -= drinks;
+            }
+        }
+
+        pairs
+            .into_iter()
+            .filter(|(_, balance)| *balance != 0)
+            .map(|((a, b), balance)| {
+                if balance > 0 {
+                    Debt {
+                        debtor: b,
+                        creditor: a,
+                        drinks: balance as u64,
+                    }
+                } else {
+                    Debt {
+                        debtor: a,
+                        creditor: b,
+                        drinks: (-balance) as u64,
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+/// Key of the document holding the drink ledgers, which cannot collide with counter keys.
+const LEDGERS_KEY: &str = "drink.ledgers";
+
+/// Provide per-chat drink ledgers backed by a storage.
+///
+/// Like counters, records only update the ledgers in memory, and a background task saves them
+/// into the storage shortly after they change.
+pub struct LedgerService {
+    /// The drink ledgers, keyed by chat ID.
+    ledgers: Document<HashMap<i64, ChatLedger>>,
+}
+
+impl LedgerService {
+    /// Create a new ledger service backed by the given storage.
+    ///
+    /// An error is returned if the ledgers cannot be loaded or parsed.
+    ///
+    /// This function must be called within a Tokio runtime.
+    pub fn new(storage: Arc<dyn Storage>) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            ledgers: Document::load(storage, LEDGERS_KEY)?,
+        })
+    }
+
+    /// Record drinks poured by a user for the given recipients in the given chat.
+    ///
+    /// `from` and the elements of `to` are the IDs and the display names of the user who poured
+    /// the drinks and the users who received the drinks, respectively.
+    pub fn record(&self, chat_id: i64, from: (i64, &str), to: &[(i64, &str)]) {
+        self.ledgers.update(|ledgers| {
+            let ledger = ledgers.entry(chat_id).or_default();
+            ledger.names.insert(from.0, from.1.to_owned());
+            for (to_id, to_name) in to {
+                ledger.names.insert(*to_id, (*to_name).to_owned());
+                ledger
+                    .drinks
+                    .entry(from.0)
+                    .or_default()
+                    .entry(*to_id)
+                    .or_default()
+                    .poured += 1;
+            }
+        });
+    }
+
+    /// Record that a user accepted or declined a drink poured for them by the given user in the
+    /// given chat.
+    ///
+    /// `user` is the ID and the display name of the user.
+    pub fn record_response(&self, chat_id: i64, from: i64, user: (i64, &str), accepted: bool) {
+        self.ledgers.update(|ledgers| {
+            let ledger = ledgers.entry(chat_id).or_default();
+            ledger.names.insert(user.0, user.1.to_owned());
+            let responses = ledger.responses.entry(user.0).or_default();
+            if accepted {
+                responses.accepted += 1;
+            } else {
+                responses.declined += 1;
+                ledger
+                    .drinks
+                    .entry(from)
+                    .or_default()
+                    .entry(user.0)
+                    .or_default()
+                    .declined += 1;
+            }
+        });
+    }
+
+    /// Get the drink ledger of the given chat.
+    pub fn get(&self, chat_id: i64) -> ChatLedger {
+        self.ledgers
+            .read(|ledgers| ledgers.get(&chat_id).cloned().unwrap_or_default())
+    }
+
+    /// Save the pending changes of the ledgers into the storage.
+    ///
+    /// This function blocks until the changes are saved.
+    pub fn flush(&self) {
+        self.ledgers.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pour(ledger: &mut ChatLedger, from: i64, to: i64, poured: u64, declined: u64) {
+        ledger
+            .drinks
+            .entry(from)
+            .or_default()
+            .insert(to, PairTally { poured, declined });
+    }
+
+    #[test]
+    fn debts_are_net_of_drinks_given_back() {
+        let mut ledger = ChatLedger::default();
+        pour(&mut ledger, 1, 2, 3, 0);
+        pour(&mut ledger, 2, 1, 1, 0);
+
+        let debts = ledger.debts();
+        assert_eq!(debts.len(), 1);
+        assert_eq!(
+            (debts[0].debtor, debts[0].creditor, debts[0].drinks),
+            (2, 1, 2)
+        );
+    }
+
+    #[test]
+    fn declined_drinks_are_not_owed() {
+        let mut ledger = ChatLedger::default();
+        pour(&mut ledger, 1, 2, 2, 2);
+        pour(&mut ledger, 3, 2, 2, 1);
+
+        let debts = ledger.debts();
+        assert_eq!(debts.len(), 1);
+        assert_eq!(
+            (debts[0].debtor, debts[0].creditor, debts[0].drinks),
+            (2, 3, 1)
+        );
+        // Declined drinks still count as poured in the statistics.
+        assert_eq!(ledger.stats()[&2].received, 4);
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+use teloxide::types::User;
+
+use crate::services::document::Document;
+use crate::services::storage::Storage;
+
+/// Key of the document holding the users seen in each chat, which cannot collide with counter
+/// keys.
+const MEMBERS_KEY: &str = "chat.members";
+
+/// Provide per-chat directories of the users seen in each chat, backed by a storage.
+///
+/// Like counters, new users only update the directories in memory, and a background task saves
+/// them into the storage shortly after they change.
+///
+/// The service also tracks when each user last sent a message in each chat, within a given number
+/// of hours. The activity is kept in memory only and is lost when the bot restarts.
+pub struct MemberService {
+    /// The users seen in each chat, keyed by chat ID and then by user ID.
+    members: Document<HashMap<i64, HashMap<i64, User>>>,
+
+    /// When each user last sent a message in each chat, keyed by chat ID and then by user ID.
+    last_active: Mutex<HashMap<i64, HashMap<i64, u64>>>,
+
+    /// How long the activity of a user is tracked, in seconds.
+    active_seconds: u64,
+}
+
+impl MemberService {
+    /// Create a new member service backed by the given storage, tracking the activity of the
+    /// users within the given number of hours.
+    ///
+    /// An error is returned if the users cannot be loaded or parsed.
+    ///
+    /// This function must be called within a Tokio runtime.
+    pub fn new(storage: Arc<dyn Storage>, active_hours: u64) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            members: Document::load(storage, MEMBERS_KEY)?,
+            last_active: Mutex::new(HashMap::new()),
+            active_seconds: active_hours.saturating_mul(3600),
+        })
+    }
+
+    /// Record the given users as seen in the given chat.
+    ///
+    /// The users are saved only if any user is new or has changed their names.
+    pub fn record<'a, I>(&self, chat_id: i64, users: I)
+    where
+        I: IntoIterator<Item = &'a User>,
+    {
+        let changed: Vec<_> = self.members.read(|members| {
+            let chat_members = members.get(&chat_id);
+            users
+                .into_iter()
+                .filter(|user| chat_members.and_then(|m| m.get(&user.id)) != Some(*user))
+                .collect()
+        });
+        if changed.is_empty() {
+            return;
+        }
+
+        self.members.update(|members| {
+            let chat_members = members.entry(chat_id).or_default();
+            for user in changed {
+                chat_members.insert(user.id, user.clone());
+            }
+        });
+    }
+
+    /// Find a user seen in the given chat by their username.
+    ///
+    /// The username is given without the leading `@` and is matched case-insensitively.
+    pub fn find_by_username(&self, chat_id: i64, username: &str) -> Option<User> {
+        self.members.read(|members| {
+            members
+                .get(&chat_id)?
+                .values()
+                .find_map(|user| match &user.username {
+                    Some(name) if name.eq_ignore_ascii_case(username) => Some(user.clone()),
+                    _ => None,
+                })
+        })
+    }
+
+    /// Record that the given user sent a message in the given chat just now.
+    ///
+    /// The users of the chat who have not sent messages within the tracked hours are forgotten.
+    pub fn mark_active(&self, chat_id: i64, user_id: i64) {
+        let now = crate::utils::get_unix_timestamp();
+        let since = now.saturating_sub(self.active_seconds);
+
+        let mut last_active = self.last_active.lock().unwrap();
+        let chat_active = last_active.entry(chat_id).or_default();
+        chat_active.retain(|_, time| *time >= since);
+        chat_active.insert(user_id, now);
+    }
+
+    /// Get the users who sent messages in the given chat at or after the given time.
+    ///
+    /// `since` is given in seconds since the Unix epoch. The users are sorted by the time they
+    /// were last active, the most recent first.
+    pub fn active_members(&self, chat_id: i64, since: u64) -> Vec<User> {
+        let mut active: Vec<_> = {
+            let last_active = self.last_active.lock().unwrap();
+            match last_active.get(&chat_id) {
+                Some(chat_active) => chat_active
+                    .iter()
+                    .filter(|(_, time)| **time >= since)
+                    .map(|(user_id, time)| (*user_id, *time))
+                    .collect(),
+                None => return Vec::new(),
+            }
+        };
+        active.sort_by_key(|(_, time)| std::cmp::Reverse(*time));
+
+        self.members.read(|members| match members.get(&chat_id) {
+            Some(chat_members) => active
+                .into_iter()
+                .filter_map(|(user_id, _)| chat_members.get(&user_id).cloned())
+                .collect(),
+            None => Vec::new(),
+        })
+    }
+
+    /// Save the pending changes of the users seen in each chat into the storage.
+    ///
+    /// This function blocks until the changes are saved.
+    pub fn flush(&self) {
+        self.members.flush();
+    }
+}
@@ -1 +1,11 @@
 pub mod counter;
+pub mod document;
+pub mod flusher;
+pub mod inventory;
+pub mod ledger;
+pub mod members;
+pub mod persist;
+pub mod pet;
+pub mod preferences;
+pub mod sanitizer;
+pub mod storage;
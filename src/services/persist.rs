@@ -0,0 +1,16 @@
+use std::io::Write;
+use std::path::Path;
+
+/// Write the given content into the given file atomically.
+///
+/// The content is written into a temporary file next to the given file, which then replaces the
+/// given file. Thus the given file is never left truncated, even if the process crashes.
+pub fn write_atomic(path: &Path, content: &str) -> std::io::Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+
+    let mut file = std::fs::File::create(&temp_path)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&temp_path, path)
+}
@@ -0,0 +1,116 @@
+use std::fmt::{Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::PetTraits;
+
+/// Maximum value of the hunger and the energy of a pet.
+const MAX_LEVEL: f64 = 100.0;
+
+/// A pet fed while its hunger is below this level is overfed.
+const OVERFED_HUNGER: f64 = 10.0;
+
+/// The state of a simulated pet.
+///
+/// The hunger and the energy decay over time. Rather than updating them periodically, the state
+/// records when it was last updated and applies the decay since then when it is read.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PetState {
+    /// The hunger of the pet, from 0 (full) to 100 (starving).
+    pub hunger: f64,
+
+    /// The energy of the pet, from 0 (exhausted) to 100.
+    pub energy: f64,
+
+    /// When the state was last updated, in seconds since the Unix epoch.
+    pub updated_at: u64,
+
+    /// Until when the pet stays grumpy, in seconds since the Unix epoch.
+    #[serde(default)]
+    pub grumpy_until: u64,
+}
+
+/// The mood of a pet.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Mood {
+    /// The pet was overfed recently.
+    Grumpy,
+
+    /// The pet is hungry.
+    Hungry,
+
+    /// The pet is low on energy.
+    Sleepy,
+
+    /// The pet is full of energy.
+    Energetic,
+
+    /// None of the above.
+    Calm,
+}
+
+impl Display for Mood {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Grumpy => "暴躁",
+            Self::Hungry => "饥饿",
+            Self::Sleepy => "困倦",
+            Self::Energetic => "精神",
+            Self::Calm => "平静",
+        };
+        f.write_str(s)
+    }
+}
+
+impl PetState {
+    /// Create the state of a newly born pet at the given time.
+    pub fn new(now: u64) -> Self {
+        Self {
+            hunger: MAX_LEVEL / 2.0,
+            energy: MAX_LEVEL / 2.0,
+            updated_at: now,
+            grumpy_until: 0,
+        }
+    }
+
+    /// Apply the decay of the hunger and the energy since the last update up to the given time.
+    pub fn decay(&mut self, traits: &PetTraits, now: u64) {
+        let hours = now.saturating_sub(self.updated_at) as f64 / 3600.0;
+        self.hunger = (self.hunger + hours * traits.hunger_per_hour as f64).min(MAX_LEVEL);
+        self.energy = (self.energy - hours * traits.energy_per_hour as f64).max(0.0);
+        self.updated_at = self.updated_at.max(now);
+    }
+
+    /// Feed the pet at the given time with food of the given satiety and energy.
+    ///
+    /// The pet becomes grumpy if it is fed while it is already full. This function returns whether
+    /// the pet is overfed.
+    pub fn feed(&mut self, traits: &PetTraits, satiety: u32, energy: u32, now: u64) -> bool {
+        self.decay(traits, now);
+
+        let overfed = satiety > 0 && self.hunger < OVERFED_HUNGER;
+        if overfed {
+            self.grumpy_until = now + traits.grumpy_minutes * 60;
+        }
+        self.hunger = (self.hunger - satiety as f64).max(0.0);
+        self.energy = (self.energy + energy as f64).min(MAX_LEVEL);
+        overfed
+    }
+
+    /// Get the mood of the pet at the given time.
+    ///
+    /// The state should have been decayed up to the given time.
+    pub fn mood(&self, now: u64) -> Mood {
+        if now < self.grumpy_until {
+            Mood::Grumpy
+        } else if self.hunger >= 70.0 {
+            Mood::Hungry
+        } else if self.energy < 30.0 {
+            Mood::Sleepy
+        } else if self.energy >= 70.0 {
+            Mood::Energetic
+        } else {
+            Mood::Calm
+        }
+    }
+}
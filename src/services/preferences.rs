@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::services::document::Document;
+use crate::services::storage::Storage;
+
+/// Key of the document holding the per-chat preferences, which cannot collide with counter keys.
+const PREFERENCES_KEY: &str = "chat.preferences";
+
+/// Preferences of a single chat.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ChatPreferences {
+    /// Name of the canteen set chosen by the chat.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canteen_set: Option<String>,
+}
+
+/// Provide per-chat preferences backed by a storage.
+///
+/// Like counters, updates only change the preferences in memory, and a background task saves them
+/// into the storage shortly after they change.
+pub struct PreferenceService {
+    /// The preferences of each chat, keyed by chat ID.
+    preferences: Document<HashMap<i64, ChatPreferences>>,
+}
+
+impl PreferenceService {
+    /// Create a new preference service backed by the given storage.
+    ///
+    /// An error is returned if the preferences cannot be loaded or parsed.
+    ///
+    /// This function must be called within a Tokio runtime.
+    pub fn new(storage: Arc<dyn Storage>) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            preferences: Document::load(storage, PREFERENCES_KEY)?,
+        })
+    }
+
+    /// Get the preferences of the given chat.
+    pub fn get(&self, chat_id: i64) -> ChatPreferences {
+        self.preferences
+            .read(|preferences| preferences.get(&chat_id).cloned().unwrap_or_default())
+    }
+
+    /// Update the preferences of the given chat.
+    pub fn update<F>(&self, chat_id: i64, f: F)
+    where
+        F: FnOnce(&mut ChatPreferences),
+    {
+        self.preferences
+            .update(|preferences| f(preferences.entry(chat_id).or_default()));
+    }
+
+    /// Save the pending changes of the preferences into the storage.
+    ///
+    /// This function blocks until the changes are saved.
+    pub fn flush(&self) {
+        self.preferences.flush();
+    }
+}
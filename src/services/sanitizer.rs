@@ -0,0 +1,218 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use crate::config::SanitizerConfig;
+
+/// Sanitize free-text arguments of commands before they are echoed back to the chat.
+#[derive(Clone, Debug)]
+pub struct Sanitizer {
+    max_length: usize,
+
+    /// The blocked words, in lowercase and without whitespace.
+    blocked_words: Vec<String>,
+}
+
+impl Sanitizer {
+    /// Create a new sanitizer from the given configuration.
+    pub fn new(config: &SanitizerConfig) -> Self {
+        Self {
+            max_length: config.max_length,
+            blocked_words: config
+                .blocked_words
+                .iter()
+                .map(|w| normalize_for_matching(w))
+                .filter(|w| !w.is_empty())
+                .collect(),
+        }
+    }
+
+    /// Sanitize the given free-text argument.
+    ///
+    /// Control and invisible formatting characters are removed, except zero-width joiners
+    /// combining emoji into a single one, and consecutive whitespace is
+    /// collapsed into a single space. The result is rejected if it is longer than the length
+    /// limit or contains a blocked word. Otherwise, `@` and leading `/` are replaced by their
+    /// full-width forms so that the echoed text cannot mention users or invoke commands.
+    pub fn sanitize(&self, input: &str) -> Result<String, SanitizeError> {
+        let chars: Vec<_> = input
+            .chars()
+            .map(|c| if c.is_whitespace() { ' ' } else { c })
+            .collect();
+        let cleaned: String = chars
+            .iter()
+            .enumerate()
+            .filter(|(i, c)| !c.is_control() && (!is_invisible(**c) || joins_emoji(&chars, *i)))
+            .map(|(_, c)| *c)
+            .collect();
+        let cleaned = cleaned.split_whitespace().collect::<Vec<_>>().join(" ");
+
+        if cleaned.chars().count() > self.max_length {
+            return Err(SanitizeError::TooLong(self.max_length));
+        }
+
+        let normalized = normalize_for_matching(&cleaned);
+        if self
+            .blocked_words
+            .iter()
+            .any(|w| normalized.contains(w.as_str()))
+        {
+            return Err(SanitizeError::Blocked);
+        }
+
+        Ok(escape(&cleaned))
+    }
+}
+
+/// Error raised when an argument is rejected by the sanitizer.
+#[derive(Clone, Debug)]
+pub enum SanitizeError {
+    /// The argument is longer than the given number of characters.
+    TooLong(usize),
+
+    /// The argument contains a blocked word.
+    Blocked,
+}
+
+impl Display for SanitizeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooLong(max) => write!(f, "输入太长了，最多 {} 个字符", max),
+            Self::Blocked => write!(f, "输入包含不允许的内容"),
+        }
+    }
+}
+
+impl Error for SanitizeError {}
+
+/// Determine whether the given character is an invisible formatting character, e.g. a zero-width
+/// space or a bidirectional override.
+fn is_invisible(c: char) -> bool {
+    matches!(
+        c,
+        '\u{00AD}'
+            | '\u{034F}'
+            | '\u{061C}'
+            | '\u{115F}'
+            | '\u{1160}'
+            | '\u{17B4}'
+            | '\u{17B5}'
+            | '\u{180E}'
+            | '\u{200B}'..='\u{200F}'
+            | '\u{202A}'..='\u{202E}'
+            | '\u{2060}'..='\u{206F}'
+            | '\u{3164}'
+            | '\u{FE00}'..='\u{FE0E}'
+            | '\u{FEFF}'
+            | '\u{FFA0}'
+    )
+}
+
+/// Determine whether the character at the given index is a zero-width joiner between emoji, e.g.
+/// in the family emoji, which must be kept for the emoji to render as a single one.
+fn joins_emoji(chars: &[char], i: usize) -> bool {
+    chars[i] == '\u{200D}'
+        && i > 0
+        && (is_emoji(chars[i - 1]) || chars[i - 1] == '\u{FE0F}')
+        && chars.get(i + 1).is_some_and(|c| is_emoji(*c))
+}
+
+/// Determine whether the given character is an emoji or a pictograph.
+fn is_emoji(c: char) -> bool {
+    matches!(
+        c,
+        '\u{2300}'..='\u{23FF}'
+            | '\u{2600}'..='\u{27BF}'
+            | '\u{2B00}'..='\u{2BFF}'
+            | '\u{1F000}'..='\u{1FAFF}'
+    )
+}
+
+/// Normalize the given text for matching blocked words.
+fn normalize_for_matching(text: &str) -> String {
+    text.chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Replace the characters that make Telegram clients render mentions and commands.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let mut word_start = true;
+    for c in text.chars() {
+        match c {
+            '@' => escaped.push('＠'),
+            '/' if word_start => escaped.push('／'),
+            _ => escaped.push(c),
+        }
+        word_start = c == ' ';
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sanitizer(max_length: usize, blocked_words: &[&str]) -> Sanitizer {
+        Sanitizer::new(&SanitizerConfig {
+            max_length,
+            blocked_words: blocked_words.iter().map(|w| w.to_string()).collect(),
+        })
+    }
+
+    #[test]
+    fn strips_invisible_characters() {
+        let s = sanitizer(32, &[]);
+        assert_eq!(s.sanitize("奶\u{200B}茶\u{202E}\u{FEFF}").unwrap(), "奶茶");
+        assert_eq!(s.sanitize("a\u{200D}b").unwrap(), "ab");
+    }
+
+    #[test]
+    fn collapses_whitespace_and_strips_control_characters() {
+        let s = sanitizer(32, &[]);
+        assert_eq!(s.sanitize(" 大杯 \n\t 奶茶\u{0007} ").unwrap(), "大杯 奶茶");
+    }
+
+    #[test]
+    fn keeps_joiners_between_emoji() {
+        let s = sanitizer(32, &[]);
+        let family = "👨\u{200D}👩\u{200D}👧";
+        assert_eq!(s.sanitize(family).unwrap(), family);
+        let heart_on_fire = "❤\u{FE0F}\u{200D}🔥";
+        assert_eq!(s.sanitize(heart_on_fire).unwrap(), heart_on_fire);
+        assert_eq!(s.sanitize("🍵\u{200D}").unwrap(), "🍵");
+    }
+
+    #[test]
+    fn rejects_long_input() {
+        let s = sanitizer(5, &[]);
+        assert_eq!(s.sanitize("一二三四五").unwrap(), "一二三四五");
+        assert_eq!(s.sanitize("一二\u{200B}三四五").unwrap(), "一二三四五");
+        assert!(matches!(
+            s.sanitize("一二三四五六"),
+            Err(SanitizeError::TooLong(5))
+        ));
+    }
+
+    #[test]
+    fn rejects_blocked_words_across_whitespace_and_case() {
+        let s = sanitizer(32, &["Bad Word"]);
+        assert!(matches!(s.sanitize("badword"), Err(SanitizeError::Blocked)));
+        assert!(matches!(
+            s.sanitize("a B a D  w\u{200B}O r d"),
+            Err(SanitizeError::Blocked)
+        ));
+        assert!(s.sanitize("bad words").is_err());
+        assert!(s.sanitize("bad, word").is_ok());
+    }
+
+    #[test]
+    fn escapes_mentions_and_commands() {
+        let s = sanitizer(32, &[]);
+        assert_eq!(
+            s.sanitize("@alice /start a/b").unwrap(),
+            "＠alice ／start a/b"
+        );
+    }
+}
@@ -0,0 +1,41 @@
+mod file;
+mod memory;
+mod sqlite;
+
+use std::error::Error;
+use std::sync::Arc;
+
+use crate::config::StorageConfig;
+
+pub use crate::services::storage::file::FileStorage;
+pub use crate::services::storage::memory::MemoryStorage;
+pub use crate::services::storage::sqlite::SqliteStorage;
+
+/// A storage backend holding the state of the bot.
+///
+/// A backend stores documents, which are replaced as a whole, and logs, which records are only
+/// appended to. Both are identified by keys consisting of ASCII letters, digits, underscores,
+/// hyphens and dots.
+pub trait Storage: Send + Sync {
+    /// Load the document with the given key, or `None` if there is no such document.
+    fn load(&self, key: &str) -> Result<Option<String>, Box<dyn Error>>;
+
+    /// Replace the document with the given key atomically.
+    fn save(&self, key: &str, content: &str) -> Result<(), Box<dyn Error>>;
+
+    /// Load the records of the log with the given key, in the order they were appended.
+    fn load_log(&self, key: &str) -> Result<Vec<String>, Box<dyn Error>>;
+
+    /// Append the given records, which must not contain line breaks, to the log with the given key.
+    fn append_log(&self, key: &str, records: &[String]) -> Result<(), Box<dyn Error>>;
+}
+
+/// Open the storage backend selected by the given configuration.
+pub fn open(config: &StorageConfig) -> Result<Arc<dyn Storage>, Box<dyn Error>> {
+    let storage: Arc<dyn Storage> = match config {
+        StorageConfig::File { path } => Arc::new(FileStorage::open(path)?),
+        StorageConfig::Sqlite { path } => Arc::new(SqliteStorage::open(path)?),
+        StorageConfig::Memory => Arc::new(MemoryStorage::new()),
+    };
+    Ok(storage)
+}
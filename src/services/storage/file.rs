@@ -0,0 +1,60 @@
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use crate::services::persist::write_atomic;
+use crate::services::storage::Storage;
+
+/// Store each document and each log in a file named after its key in a directory.
+///
+/// Logs are stored as text files with a record per line.
+pub struct FileStorage {
+    dir: PathBuf,
+}
+
+impl FileStorage {
+    /// Open the storage in the given directory, which is created if it does not exist.
+    pub fn open(dir: &Path) -> Result<Self, Box<dyn Error>> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_owned(),
+        })
+    }
+}
+
+impl Storage for FileStorage {
+    fn load(&self, key: &str) -> Result<Option<String>, Box<dyn Error>> {
+        match std::fs::read_to_string(self.dir.join(key)) {
+            Ok(content) => Ok(Some(content)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save(&self, key: &str, content: &str) -> Result<(), Box<dyn Error>> {
+        write_atomic(&self.dir.join(key), content)?;
+        Ok(())
+    }
+
+    fn load_log(&self, key: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let content = self.load(key)?.unwrap_or_default();
+        Ok(content.lines().map(String::from).collect())
+    }
+
+    fn append_log(&self, key: &str, records: &[String]) -> Result<(), Box<dyn Error>> {
+        let mut content = String::new();
+        for record in records {
+            content.push_str(record);
+            content.push('\n');
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(key))?;
+        file.write_all(content.as_bytes())?;
+        file.sync_data()?;
+        Ok(())
+    }
+}
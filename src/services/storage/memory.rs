@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Mutex;
+
+use crate::services::storage::Storage;
+
+/// Keep the documents and the logs in memory, e.g. for tests. Nothing survives a restart.
+#[derive(Default)]
+pub struct MemoryStorage {
+    documents: Mutex<HashMap<String, String>>,
+    logs: Mutex<HashMap<String, Vec<String>>>,
+}
+
+impl MemoryStorage {
+    /// Create an empty storage.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn load(&self, key: &str) -> Result<Option<String>, Box<dyn Error>> {
+        Ok(self.documents.lock().unwrap().get(key).cloned())
+    }
+
+    fn save(&self, key: &str, content: &str) -> Result<(), Box<dyn Error>> {
+        self.documents
+            .lock()
+            .unwrap()
+            .insert(key.to_owned(), content.to_owned());
+        Ok(())
+    }
+
+    fn load_log(&self, key: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(self
+            .logs
+            .lock()
+            .unwrap()
+            .get(key)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn append_log(&self, key: &str, records: &[String]) -> Result<(), Box<dyn Error>> {
+        self.logs
+            .lock()
+            .unwrap()
+            .entry(key.to_owned())
+            .or_default()
+            .extend_from_slice(records);
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+use std::error::Error;
+use std::path::Path;
+use std::sync::Mutex;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::services::storage::Storage;
+
+/// The schema migrations, in order. The schema version of a database, stored in its `user_version`,
+/// is the number of migrations applied to it.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE documents (
+        key TEXT PRIMARY KEY NOT NULL,
+        content TEXT NOT NULL
+    );
+    CREATE TABLE logs (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        key TEXT NOT NULL,
+        record TEXT NOT NULL
+    );",
+    "CREATE INDEX logs_key ON logs (key, id);",
+];
+
+/// Store the documents and the logs in an embedded SQLite database.
+pub struct SqliteStorage {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    /// Open the database in the given file, which is created if it does not exist, and migrate it
+    /// to the latest schema.
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut conn = Connection::open(path)?;
+        migrate(&mut conn)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+/// Apply the migrations that are not applied to the given database yet.
+fn migrate(conn: &mut Connection) -> Result<(), Box<dyn Error>> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version > MIGRATIONS.len() {
+        return Err(format!(
+            "database schema version {} is newer than the supported version {}",
+            version,
+            MIGRATIONS.len()
+        )
+        .into());
+    }
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+        log::info!("Migrated database schema to version {}", i + 1);
+    }
+    Ok(())
+}
+
+impl Storage for SqliteStorage {
+    fn load(&self, key: &str) -> Result<Option<String>, Box<dyn Error>> {
+        let conn = self.conn.lock().unwrap();
+        let content = conn
+            .query_row(
+                "SELECT content FROM documents WHERE key = ?1",
+                params![key],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(content)
+    }
+
+    fn save(&self, key: &str, content: &str) -> Result<(), Box<dyn Error>> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO documents (key, content) VALUES (?1, ?2)
+             ON CONFLICT (key) DO UPDATE SET content = excluded.content",
+            params![key, content],
+        )?;
+        Ok(())
+    }
+
+    fn load_log(&self, key: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT record FROM logs WHERE key = ?1 ORDER BY id")?;
+        let records = stmt
+            .query_map(params![key], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(records)
+    }
+
+    fn append_log(&self, key: &str, records: &[String]) -> Result<(), Box<dyn Error>> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        {
+            let mut stmt = tx.prepare("INSERT INTO logs (key, record) VALUES (?1, ?2)")?;
+            for record in records {
+                stmt.execute(params![key, record])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
@@ -1,4 +1,28 @@
-use teloxide::types::{ForwardKind, Message, MessageKind, User};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use teloxide::types::{ForwardKind, Message, MessageEntity, MessageEntityKind, MessageKind, User};
+
+/// Maximum number of characters in a Telegram text message.
+pub const MAX_MESSAGE_LENGTH: usize = 4096;
+
+/// Get the current time in seconds since the Unix epoch.
+pub fn get_unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Get the index of the day since the Unix epoch containing the given time, where days start at
+/// midnight in the time zone of the given UTC offset in seconds.
+pub fn local_day(time: u64, utc_offset: i64) -> u64 {
+    (time as i64 + utc_offset).max(0) as u64 / 86400
+}
+
+/// Get the time of the midnight starting the given day, see `local_day`.
+pub fn local_day_start(day: u64, utc_offset: i64) -> u64 {
+    (day as i64 * 86400 - utc_offset).max(0) as u64
+}
 
 /// Get the sender of the given Telegram message.
 ///
@@ -21,6 +45,22 @@ pub fn get_user_display_name(u: &User) -> String {
     }
 }
 
+/// Get an HTML mention of the user.
+///
+/// Users with usernames are mentioned by their usernames. Other users are mentioned by links to
+/// their IDs, so that they are notified as well.
+pub fn get_user_mention(u: &User) -> String {
+    match &u.username {
+        Some(username) => format!("@{}", username),
+        None => teloxide::utils::html::user_mention(u.id, &get_user_display_name(u)),
+    }
+}
+
+/// Escape the given text for messages sent in the HTML parse mode.
+pub fn escape_html(text: &str) -> String {
+    teloxide::utils::html::escape(text)
+}
+
 /// Get the replied message of the given Telegram message.
 ///
 /// This function returns `None` if the given message does not reply to a message.
@@ -33,3 +73,182 @@ pub fn get_replied_message(message: &Message) -> Option<&Message> {
         _ => None,
     }
 }
+
+/// Get the text covered by the given entity of a message text.
+///
+/// This function returns `None` if the entity lies outside of the text.
+pub fn get_entity_text<'a>(text: &'a str, entity: &MessageEntity) -> Option<&'a str> {
+    let (start, end) =
+        utf16_range_to_byte_range(text, entity.offset, entity.offset + entity.length)?;
+    Some(&text[start..end])
+}
+
+/// Get the text of the given message with all the mentions removed.
+pub fn get_text_without_mentions(message: &Message) -> Option<String> {
+    let text = message.text()?;
+    Some(remove_mentions(
+        text,
+        message.entities().unwrap_or_default(),
+    ))
+}
+
+/// Remove the mentions among the given entities from the given text.
+fn remove_mentions(text: &str, entities: &[MessageEntity]) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut last = 0;
+    for entity in entities {
+        if !matches!(
+            entity.kind,
+            MessageEntityKind::Mention | MessageEntityKind::TextMention { .. }
+        ) {
+            continue;
+        }
+        if let Some((start, end)) =
+            utf16_range_to_byte_range(text, entity.offset, entity.offset + entity.length)
+        {
+            if start >= last {
+                result.push_str(&text[last..start]);
+                last = end;
+            }
+        }
+    }
+    result.push_str(&text[last..]);
+    result
+}
+
+/// Convert a range of UTF-16 code unit offsets into a range of byte offsets of the given text.
+fn utf16_range_to_byte_range(text: &str, start: usize, end: usize) -> Option<(usize, usize)> {
+    let mut utf16_offset = 0;
+    let mut byte_start = None;
+    for (byte_offset, c) in text.char_indices() {
+        if utf16_offset == start {
+            byte_start = Some(byte_offset);
+        }
+        if utf16_offset == end {
+            return Some((byte_start?, byte_offset));
+        }
+        utf16_offset += c.len_utf16();
+    }
+    if utf16_offset == start {
+        byte_start = Some(text.len());
+    }
+    if utf16_offset == end {
+        return Some((byte_start?, text.len()));
+    }
+    None
+}
+
+/// Join the given items with the separator into chunks of at most `limit` characters each.
+///
+/// Items are never split across chunks; an item longer than the limit forms a chunk by itself.
+pub fn join_into_chunks<I, T>(items: I, separator: &str, limit: usize) -> Vec<String>
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<str>,
+{
+    let separator_len = separator.chars().count();
+    let mut chunks = Vec::new();
+    let mut chunk = String::new();
+    let mut chunk_len = 0;
+    for item in items {
+        let item = item.as_ref();
+        let item_len = item.chars().count();
+        if !chunk.is_empty() && chunk_len + separator_len + item_len > limit {
+            chunks.push(std::mem::take(&mut chunk));
+            chunk_len = 0;
+        }
+        if !chunk.is_empty() {
+            chunk.push_str(separator);
+            chunk_len += separator_len;
+        }
+        chunk.push_str(item);
+        chunk_len += item_len;
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mention(offset: usize, length: usize) -> MessageEntity {
+        MessageEntity::new(MessageEntityKind::Mention, offset, length)
+    }
+
+    #[test]
+    fn local_days_start_at_local_midnight() {
+        let utc8 = 8 * 3600;
+        // 1970-01-01 15:59:59 UTC is 23:59:59 in UTC+8.
+        assert_eq!(local_day(16 * 3600 - 1, utc8), 0);
+        assert_eq!(local_day(16 * 3600, utc8), 1);
+        assert_eq!(local_day_start(1, utc8), 16 * 3600);
+        assert_eq!(local_day(86400, -3600), 0);
+        assert_eq!(local_day_start(1, -3600), 86400 + 3600);
+    }
+
+    #[test]
+    fn utf16_range_with_non_bmp_characters() {
+        // "🍵" takes 2 UTF-16 code units and 4 bytes.
+        let text = "🍵 @alice";
+        assert_eq!(utf16_range_to_byte_range(text, 3, 9), Some((5, 11)));
+        assert_eq!(utf16_range_to_byte_range(text, 0, 2), Some((0, 4)));
+        // Offsets inside a surrogate pair do not map to any byte offset.
+        assert_eq!(utf16_range_to_byte_range(text, 1, 2), None);
+    }
+
+    #[test]
+    fn utf16_range_ending_at_end_of_text() {
+        let text = "hi @bob";
+        assert_eq!(utf16_range_to_byte_range(text, 3, 7), Some((3, 7)));
+        assert_eq!(utf16_range_to_byte_range(text, 7, 7), Some((7, 7)));
+        assert_eq!(utf16_range_to_byte_range(text, 3, 8), None);
+    }
+
+    #[test]
+    fn remove_mentions_after_emoji() {
+        let text = "/round 🍵🍵 @alice 奶茶";
+        let entities = [mention(12, 6)];
+        assert_eq!(remove_mentions(text, &entities), "/round 🍵🍵  奶茶");
+    }
+
+    #[test]
+    fn remove_mentions_containing_emoji() {
+        // A text mention may cover a display name containing emoji.
+        let text = "给 Alice🐱 一杯";
+        let entities = [MessageEntity::new(
+            MessageEntityKind::TextMention {
+                user: User {
+                    id: 1,
+                    is_bot: false,
+                    first_name: String::from("Alice🐱"),
+                    last_name: None,
+                    username: None,
+                    language_code: None,
+                },
+            },
+            2,
+            7,
+        )];
+        assert_eq!(remove_mentions(text, &entities), "给  一杯");
+    }
+
+    #[test]
+    fn remove_mentions_at_end_of_text() {
+        let text = "/producedrink 奶茶 @alice @bob";
+        let entities = [mention(17, 6), mention(24, 4)];
+        assert_eq!(remove_mentions(text, &entities), "/producedrink 奶茶  ");
+    }
+
+    #[test]
+    fn remove_mentions_ignores_other_entities() {
+        let text = "#tag @alice";
+        let entities = [
+            MessageEntity::new(MessageEntityKind::Hashtag, 0, 4),
+            mention(5, 6),
+        ];
+        assert_eq!(remove_mentions(text, &entities), "#tag ");
+    }
+}